@@ -0,0 +1,168 @@
+//! Last downloaded copies of waist channels, shown before the server answers
+use geojson::GeoJson;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+pub struct CachedChannel {
+    /// Entity tag of the server's response, sent back to download only changed channels
+    pub etag: Option<String>,
+    /// Cursor of the server's response to download only later changes, absent in copies saved before cursors
+    #[serde(default)]
+    pub cursor: Option<i64>,
+    pub json: GeoJson,
+}
+
+/// Storage key of the channel, safe to be a file name
+fn key(server: &str, id: &str) -> String {
+    format!("{}/{}", server, id)
+        .chars()
+        .map(|x| {
+            if x.is_ascii_alphanumeric() || x == '-' || x == '.' {
+                x
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const CACHE_DIR: &str = "channels_cache";
+
+#[cfg(not(target_arch = "wasm32"))]
+fn path(server: &str, id: &str) -> std::path::PathBuf {
+    std::path::Path::new(CACHE_DIR).join(format!("{}.json", key(server, id)))
+}
+
+/// Copy of the channel, it is read and parsed on a blocking thread of the runtime
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn load(server: &str, id: &str) -> Option<CachedChannel> {
+    let path = path(server, id);
+
+    tokio::task::spawn_blocking(move || {
+        let data = std::fs::read(&path).ok()?;
+
+        match serde_json::from_slice::<CachedChannel>(&data) {
+            Ok(cached) => Some(cached),
+            Err(err) => {
+                log::warn!("Cache {} not loaded: {}", path.display(), err);
+                None
+            }
+        }
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+/// Replace the copy of the channel, it is serialized and written on a blocking thread of the runtime
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn store(server: &str, id: &str, cached: CachedChannel) {
+    let path = path(server, id);
+    let temp_path = path.with_extension("tmp");
+
+    let result = tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+        let data = serde_json::to_vec(&cached)?;
+
+        std::fs::create_dir_all(CACHE_DIR)?;
+        /* the copy is replaced at once, an interrupted write does not spoil it */
+        std::fs::write(&temp_path, data)?;
+        std::fs::rename(&temp_path, &path)
+    })
+    .await;
+
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => log::error!("Cache of {} not saved: {}", id, err),
+        Err(err) => log::error!("Cache of {} not saved: {}", id, err),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn remove(server: &str, id: &str) {
+    let _ = std::fs::remove_file(path(server, id));
+}
+
+#[cfg(target_arch = "wasm32")]
+const CACHE_KEY_PREFIX: &str = "channel_cache/";
+
+/// Longest copy kept in local storage, in characters: the whole site has about 5 MB,
+/// the config with pending uploads needs room too
+#[cfg(target_arch = "wasm32")]
+const CACHE_MAX_LENGTH: usize = 1024 * 1024;
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window().and_then(|window| window.local_storage().ok().flatten())
+}
+
+#[cfg(target_arch = "wasm32")]
+pub async fn load(server: &str, id: &str) -> Option<CachedChannel> {
+    let key = format!("{}{}", CACHE_KEY_PREFIX, key(server, id));
+    let data = local_storage()?.get_item(&key).ok().flatten()?;
+
+    match serde_json::from_str(&data) {
+        Ok(cached) => Some(cached),
+        Err(err) => {
+            log::warn!("Cache '{}' not loaded: {}", key, err);
+            None
+        }
+    }
+}
+
+/// Replace the copy of the channel, copies of other channels are evicted when the storage quota is exceeded
+#[cfg(target_arch = "wasm32")]
+pub async fn store(server: &str, id: &str, cached: CachedChannel) {
+    let key = format!("{}{}", CACHE_KEY_PREFIX, key(server, id));
+    let Some(local_storage) = local_storage() else {
+        return;
+    };
+    let data = match serde_json::to_string(&cached) {
+        Ok(data) => data,
+        Err(err) => {
+            log::error!("Cache '{}' not serialized: {}", key, err);
+            return;
+        }
+    };
+
+    /* the stale copy would be revalidated by its etag and downloaded again anyway */
+    if data.len() > CACHE_MAX_LENGTH {
+        log::info!("Cache '{}' not saved: channel is too large", key);
+        let _ = local_storage.remove_item(&key);
+        return;
+    }
+
+    let mut evicted = other_keys(&local_storage, &key).into_iter();
+
+    while let Err(err) = local_storage.set_item(&key, &data) {
+        match evicted.next() {
+            Some(other) => {
+                log::info!("Cache '{}' evicted to save '{}'", other, key);
+                let _ = local_storage.remove_item(&other);
+            }
+            None => {
+                log::error!("Cache '{}' not saved: {:?}", key, err);
+                let _ = local_storage.remove_item(&key);
+                return;
+            }
+        }
+    }
+}
+
+/// Keys of cached copies of other channels
+#[cfg(target_arch = "wasm32")]
+fn other_keys(local_storage: &web_sys::Storage, key: &str) -> Vec<String> {
+    let length = local_storage.length().unwrap_or(0);
+
+    (0..length)
+        .filter_map(|idx| local_storage.key(idx).ok().flatten())
+        .filter(|other| other.starts_with(CACHE_KEY_PREFIX) && other != key)
+        .collect()
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn remove(server: &str, id: &str) {
+    if let Some(local_storage) = local_storage() {
+        let _ = local_storage.remove_item(&format!("{}{}", CACHE_KEY_PREFIX, key(server, id)));
+    }
+}
@@ -7,6 +7,197 @@ pub struct Config {
     pub lat_lon: Option<Position>,
     pub zoom: Option<u8>,
     pub state: Option<String>,
+    /// Name of the selected waist server profile
+    pub server: Option<String>,
+    pub servers: Option<ServerProfiles>,
+    /// JSON list of uploads not accepted by the server yet
+    pub uploads: Option<String>,
+    /// JSON list of overlay layers with their settings
+    pub layers: Option<String>,
+    pub channels: Option<RecentChannels>,
+}
+
+#[derive(PartialEq, Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ServerProfile {
+    pub name: String,
+    /// Base url of waist server without trailing slash
+    pub url: String,
+    /// API token sent as `Authorization: Bearer`, anonymous access when it is not set
+    pub token: Option<String>,
+}
+
+/// Named waist servers, stored as JSON list of profiles
+#[derive(PartialEq, Clone, Default, Debug)]
+pub struct ServerProfiles(Vec<ServerProfile>);
+
+impl ServerProfiles {
+    /// Add profile or replace url of the profile with the same name, the url must be http(s) one
+    pub fn insert(&mut self, name: &str, url: &str) -> Result<(), String> {
+        let name = name.split_whitespace().collect::<Vec<_>>().join("_");
+        let url = url.trim().trim_end_matches('/').to_string();
+
+        if name.is_empty() {
+            return Err("server profile has no name".to_string());
+        }
+
+        match reqwest::Url::parse(&url) {
+            Ok(parsed) if matches!(parsed.scheme(), "http" | "https") && parsed.has_host() => (),
+            Ok(_) => return Err(format!("'{}' is not an http(s) url", url)),
+            Err(err) => return Err(format!("'{}' is not a valid url: {}", url, err)),
+        }
+
+        match self.0.iter_mut().find(|profile| profile.name == name) {
+            Some(profile) => profile.url = url,
+            None => self.0.push(ServerProfile { name, url, token: None }),
+        }
+        Ok(())
+    }
+
+    /// Set token of the profile, empty token removes it
+    pub fn set_token(&mut self, name: &str, token: &str) {
+        let token = token.trim();
+
+        if token.contains(char::is_whitespace) {
+            return;
+        }
+
+        if let Some(profile) = self.0.iter_mut().find(|profile| profile.name == name) {
+            profile.token = Some(token.to_string()).filter(|x| !x.is_empty());
+        }
+    }
+
+    /// Token of the first profile of the server's url
+    pub fn token_of(&self, url: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .filter(|profile| profile.url == url)
+            .find_map(|profile| profile.token.as_deref())
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.0.retain(|profile| profile.name != name);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ServerProfile> {
+        self.0.iter().find(|profile| profile.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ServerProfile> {
+        self.0.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Display for ServerProfiles {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let json = serde_json::to_string(&self.0).map_err(|_| std::fmt::Error)?;
+
+        write!(f, "{}", json)
+    }
+}
+
+impl FromStr for ServerProfiles {
+    type Err = std::convert::Infallible;
+
+    /// Malformed profiles are skipped, the others are kept
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let stored: Vec<ServerProfile> = match serde_json::from_str::<Vec<serde_json::Value>>(s) {
+            Ok(values) => values
+                .into_iter()
+                .filter_map(|value| match serde_json::from_value(value) {
+                    Ok(profile) => Some(profile),
+                    Err(err) => {
+                        log::warn!("Server profile skipped: {}", err);
+                        None
+                    }
+                })
+                .collect(),
+            /* profiles saved before JSON are "name=url" or "name=url|token" pairs separated by spaces */
+            Err(_) => s
+                .split_whitespace()
+                .filter_map(|pair| {
+                    let Some((name, url)) = pair.split_once('=') else {
+                        log::warn!("Server profile '{}' skipped: no url", pair);
+                        return None;
+                    };
+                    let (url, token) = url.split_once('|').unwrap_or((url, ""));
+
+                    Some(ServerProfile {
+                        name: name.to_string(),
+                        url: url.to_string(),
+                        token: Some(token.to_string()),
+                    })
+                })
+                .collect(),
+        };
+        let mut profiles = Self::default();
+
+        for profile in stored {
+            match profiles.insert(&profile.name, &profile.url) {
+                Ok(_) => profiles.set_token(&profile.name, profile.token.as_deref().unwrap_or("")),
+                Err(err) => log::warn!("Server profile '{}' skipped: {}", profile.name, err),
+            }
+        }
+
+        Ok(profiles)
+    }
+}
+
+/// Maximum number of remembered channels
+const RECENT_CHANNELS_MAX: usize = 10;
+
+/// Recently opened waist channels, the latest first, stored as ids separated by spaces
+#[derive(PartialEq, Clone, Default, Debug)]
+pub struct RecentChannels(Vec<String>);
+
+impl RecentChannels {
+    /// Move channel to the front, the oldest channel is forgotten when the list is full
+    pub fn push(&mut self, id: &str) {
+        let id = id.trim();
+
+        if id.is_empty() || id.contains(char::is_whitespace) {
+            return;
+        }
+
+        self.0.retain(|channel| channel != id);
+        self.0.insert(0, id.to_string());
+        self.0.truncate(RECENT_CHANNELS_MAX);
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        self.0.retain(|channel| channel != id);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.0.iter()
+    }
+}
+
+impl Display for RecentChannels {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.join(" "))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct RecentChannelsParseError;
+
+impl FromStr for RecentChannels {
+    type Err = RecentChannelsParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut channels = Self::default();
+
+        /* the latest is first, pushing in reverse keeps the order */
+        for id in s.split_whitespace().rev() {
+            channels.push(id);
+        }
+
+        Ok(channels)
+    }
 }
 
 #[derive(PartialEq, Clone, Copy, Default)]
@@ -50,6 +241,54 @@ impl FromStr for Position {
     }
 }
 
+/// Characters tini takes for comments and line ends, values keep them escaped as `%XX`
+#[cfg(not(target_arch = "wasm32"))]
+const INI_ESCAPED: [char; 5] = ['%', '#', ';', '\n', '\r'];
+
+#[cfg(not(target_arch = "wasm32"))]
+fn ini_escape(value: &str) -> String {
+    value
+        .chars()
+        .map(|x| {
+            if INI_ESCAPED.contains(&x) {
+                format!("%{:02X}", x as u32)
+            } else {
+                x.to_string()
+            }
+        })
+        .collect()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn ini_unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(at) = rest.find('%') {
+        result.push_str(&rest[..at]);
+        rest = &rest[at..];
+
+        match rest
+            .get(1..3)
+            .and_then(|x| u8::from_str_radix(x, 16).ok())
+            .map(char::from)
+            .filter(|x| INI_ESCAPED.contains(x))
+        {
+            Some(x) => {
+                result.push(x);
+                rest = &rest[3..];
+            }
+            None => {
+                result.push('%');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 struct ConfigReadWriter {
     tini: Option<tini::Ini>,
@@ -91,7 +330,7 @@ impl ConfigReadWriter {
         Self {
             tini: if let Some(tini) = self.tini {
                 if let Some(value) = value {
-                    Some(tini.section("all").item(key, value))
+                    Some(tini.section("all").item(key, ini_escape(&value.to_string())))
                 } else {
                     Some(tini)
                 }
@@ -105,7 +344,10 @@ impl ConfigReadWriter {
     where
         T: FromStr,
     {
-        self.tini.as_ref().and_then(|x| x.get("all", key))
+        self.tini
+            .as_ref()
+            .and_then(|x| x.get::<String>("all", key))
+            .and_then(|x| ini_unescape(&x).parse().ok())
     }
 }
 
@@ -201,33 +443,110 @@ impl ConfigContext {
             lat_lon: reader.get("lat_lon"),
             zoom: reader.get("zoom"),
             state: reader.get("state"),
+            server: reader.get("server"),
+            servers: reader.get("servers"),
+            uploads: reader.get("uploads"),
+            layers: reader.get("layers"),
+            channels: reader.get("channels"),
         };
 
         self.previous_state = config.clone();
         config
     }
 
-    pub fn config_update(&mut self, zoom: u8, lat_lon: Option<Position>, mappainter_state: Option<String>) {
-        let new_config = Config {
-            lat_lon,
-            zoom: Some(zoom),
-            state: mappainter_state.clone(),
-        };
-
+    /// The config is due to be saved, it is once in `SAVER_GUARD_VALUE` calls
+    pub fn config_due(&mut self) -> bool {
         if self.saver_guard == 0 {
             self.saver_guard = SAVER_GUARD_VALUE;
         } else {
             self.saver_guard -= 1;
         }
 
-        if self.saver_guard == 0 && new_config != self.previous_state {
+        self.saver_guard == 0
+    }
+
+    /// Save the config when it is changed
+    pub fn config_save(&mut self, new_config: Config) {
+        if new_config != self.previous_state {
             ConfigReadWriter::new()
                 .set("zoom", new_config.zoom)
                 .set("lat_lon", new_config.lat_lon)
-                .set("state", mappainter_state)
+                .set("state", new_config.state.as_ref())
+                .set("server", new_config.server.as_ref())
+                .set("servers", new_config.servers.as_ref())
+                .set("uploads", new_config.uploads.as_ref())
+                .set("layers", new_config.layers.as_ref())
+                .set("channels", new_config.channels.as_ref())
                 .write(&self.inifile);
 
             self.previous_state = new_config;
         }
     }
 }
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uploads_with_comment_characters_are_reloaded() {
+        let path = std::env::temp_dir().join(format!("megingjord-config-{}.ini", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let uploads = r##"[{"server":"https://example.org","channel":"world","json":{"type":"Feature","geometry":null,"properties":{"color":"#ff0000","note":"a; b\n100%"}}}]"##;
+        let config = Config {
+            uploads: Some(uploads.to_string()),
+            ..Default::default()
+        };
+
+        ConfigContext::new(path.clone()).config_save(config);
+        let loaded = ConfigContext::new(path.clone()).config_load();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(loaded.uploads.as_deref(), Some(uploads));
+    }
+
+    #[test]
+    fn server_profiles_keep_separator_characters() {
+        let mut profiles = ServerProfiles::default();
+
+        profiles.insert("a=b", "https://example.org/waist").unwrap();
+        profiles.set_token("a=b", "secret|#;");
+
+        let restored: ServerProfiles = profiles.to_string().parse().unwrap();
+
+        assert_eq!(restored, profiles);
+        assert_eq!(restored.token_of("https://example.org/waist"), Some("secret|#;"));
+    }
+
+    #[test]
+    fn malformed_server_profiles_are_skipped() {
+        let profiles: ServerProfiles = r#"[{"name":"a","url":"https://a.org"},{"name":"b"}]"#.parse().unwrap();
+
+        assert_eq!(profiles.iter().map(|x| x.name.as_str()).collect::<Vec<_>>(), ["a"]);
+
+        let profiles: ServerProfiles = "a=https://a.org|secret broken c=ftp://c.org b=https://b.org"
+            .parse()
+            .unwrap();
+
+        assert_eq!(profiles.iter().map(|x| x.name.as_str()).collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(profiles.token_of("https://a.org"), Some("secret"));
+    }
+
+    #[test]
+    fn server_urls_are_validated() {
+        let mut profiles = ServerProfiles::default();
+
+        assert!(profiles.insert("a", "https://a.org/").is_ok());
+        assert!(profiles.insert("b", "a.org").is_err());
+        assert!(profiles.insert("c", "file:///etc").is_err());
+        assert!(profiles.insert(" ", "https://a.org").is_err());
+        assert_eq!(profiles.get("a").map(|x| x.url.as_str()), Some("https://a.org"));
+        assert_eq!(profiles.iter().count(), 1);
+    }
+
+    #[test]
+    fn unescaped_values_are_kept() {
+        assert_eq!(ini_unescape("a%20b%"), "a%20b%");
+        assert_eq!(ini_unescape(&ini_escape("#;%\n")), "#;%\n");
+    }
+}
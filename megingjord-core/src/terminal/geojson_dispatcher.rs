@@ -1,123 +1,482 @@
+use super::channel_cache;
+use super::config::{RecentChannels, ServerProfiles};
+use super::geojson_file;
+use super::geojson_geometry;
+use super::geojson_style::{FeatureStyle, Marker, MarkerShape};
+use super::geojson_subscription::{Subscription, SubscriptionEvent};
 use super::mappainter::Color;
-use egui::{Align2, Painter, Response, RichText, Ui, Window};
+use super::network::{Network, NetworkFuture, Spawner};
+use super::vector_tiles::{self, TileCache, TileId};
+use egui::{
+    Align2, Color32, ComboBox, FontId, Grid, Label, Mesh, Painter, PointerButton, Pos2, ProgressBar, Rect, Response,
+    RichText, Sense, Shape, Slider, Stroke, TextEdit, Ui, Vec2, Window,
+};
+use futures::future::{AbortHandle, Abortable};
 use geojson::GeoJson;
-use std::sync::{Arc, RwLock};
-use walkers::{Plugin, Projector};
+use rstar::primitives::{GeomWithData, Rectangle};
+use rstar::{RTree, AABB};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use walkers::{MapMemory, Plugin, Projector};
 
-use reqwest::{header, Client, StatusCode};
+use reqwest::{header, Client, RequestBuilder, StatusCode};
 
-struct Task {}
+/// Request with the server profile's token, anonymous when it is not set
+fn with_token(request: RequestBuilder, token: Option<&str>) -> RequestBuilder {
+    match token {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    }
+}
+
+/// Bytes moved by the task's request, shared with the UI
+#[derive(Default)]
+struct TaskProgress {
+    transferred: AtomicU64,
+    /// Expected size in bytes, zero when unknown
+    total: AtomicU64,
+    /// The request is completed or aborted
+    finished: AtomicBool,
+}
+
+/// Running network request of the entry
+struct Task {
+    abort: AbortHandle,
+    progress: Arc<TaskProgress>,
+}
+
+/// Tasks by entries' local ids
+type Tasks = Arc<Mutex<HashMap<u32, Task>>>;
+
+/// Remove completed and aborted tasks
+fn forget_finished(tasks: &mut HashMap<u32, Task>) {
+    tasks.retain(|_, task| !task.is_finished());
+}
+
+/// Size of the upload body's parts, progress is counted by parts
+#[cfg(not(target_arch = "wasm32"))]
+const UPLOAD_CHUNK_SIZE: usize = 16 * 1024;
+
+#[cfg(not(target_arch = "wasm32"))]
+fn upload_body(data: Vec<u8>, progress: Arc<TaskProgress>) -> reqwest::Body {
+    use futures::StreamExt;
+
+    progress.total.store(data.len() as u64, Ordering::Relaxed);
+
+    let chunks: Vec<Vec<u8>> = data.chunks(UPLOAD_CHUNK_SIZE).map(|x| x.to_vec()).collect();
+
+    reqwest::Body::wrap_stream(futures::stream::iter(chunks).map(move |chunk| {
+        progress.transferred.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        Ok::<_, std::io::Error>(chunk)
+    }))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn upload_body(data: Vec<u8>, progress: Arc<TaskProgress>) -> reqwest::Body {
+    /* fetch does not report progress of the request's body */
+    progress.total.store(data.len() as u64, Ordering::Relaxed);
+    data.into()
+}
+
+async fn read_body(response: reqwest::Response, progress: &TaskProgress) -> reqwest::Result<Vec<u8>> {
+    use futures::StreamExt;
+
+    let mut body = Vec::new();
+    let mut stream = response.bytes_stream();
+
+    while let Some(chunk) = stream.next().await {
+        body.extend_from_slice(&chunk?);
+        progress.transferred.store(body.len() as u64, Ordering::Relaxed);
+    }
+
+    Ok(body)
+}
 
 impl Task {
-    pub fn download(client: Client, local_id: u32, entries: &Arc<RwLock<Vec<Entry>>>, jsonid: String) -> Self {
+    fn spawn<F: NetworkFuture>(spawner: &Spawner, run: impl FnOnce(Arc<TaskProgress>) -> F) -> Self {
+        let (abort, registration) = AbortHandle::new_pair();
+        let progress: Arc<TaskProgress> = Default::default();
+        let future = Abortable::new(run(Arc::clone(&progress)), registration);
+        let finished = Arc::clone(&progress);
+
+        spawner.spawn(async move {
+            let _ = future.await;
+            finished.finished.store(true, Ordering::Relaxed);
+        });
+
+        Self { abort, progress }
+    }
+
+    pub fn download(
+        spawner: &Spawner,
+        client: Client,
+        local_id: u32,
+        entries: &Arc<RwLock<Vec<Entry>>>,
+        jsonid: String,
+    ) -> Self {
         let entries = Arc::clone(entries);
 
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            let runtime = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .unwrap();
+        Self::spawn(spawner, move |progress| {
+            Task::run_download(client, local_id, entries, jsonid, progress)
+        })
+    }
 
-            std::thread::spawn(move || {
-                runtime.block_on(async { Task::run_download(client, local_id, entries, jsonid).await })
-            });
-        }
+    pub fn upload(spawner: &Spawner, client: Client, local_id: u32, entries: &Arc<RwLock<Vec<Entry>>>) -> Self {
+        let entries = Arc::clone(entries);
 
-        #[cfg(target_arch = "wasm32")]
-        wasm_bindgen_futures::spawn_local(async move { Task::run_download(client, local_id, entries, jsonid).await });
+        Self::spawn(spawner, move |progress| {
+            Task::run_upload(client, local_id, entries, progress)
+        })
+    }
+
+    /// Ask `server` for a fresh channel id, the result is pushed to `minted`
+    pub fn new_channel(
+        spawner: &Spawner,
+        client: Client,
+        server: String,
+        token: Option<String>,
+        minted: &Arc<Mutex<Vec<MintedChannel>>>,
+    ) -> Self {
+        let minted = Arc::clone(minted);
+
+        Self::spawn(spawner, move |_| Task::run_new_channel(client, server, token, minted))
+    }
 
-        Self {}
+    /// Check whether `url` answers like a waist server, the result is pushed to `probed`
+    pub fn probe_server(
+        spawner: &Spawner,
+        client: Client,
+        name: String,
+        url: String,
+        probed: &Arc<Mutex<Vec<ProbedServer>>>,
+    ) -> Self {
+        let probed = Arc::clone(probed);
+
+        Self::spawn(spawner, move |_| Task::run_probe_server(client, name, url, probed))
     }
 
-    pub fn upload(client: Client, local_id: u32, entries: &Arc<RwLock<Vec<Entry>>>) -> Self {
+    /// Request the vector tile of the entry, the result is stored in the entry's tile cache
+    pub fn tile(
+        spawner: &Spawner,
+        client: Client,
+        local_id: u32,
+        entries: &Arc<RwLock<Vec<Entry>>>,
+        url: String,
+        token: Option<String>,
+        tile: TileId,
+    ) -> Self {
         let entries = Arc::clone(entries);
 
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            let runtime = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .unwrap();
+        Self::spawn(spawner, move |progress| {
+            Task::run_tile(client, local_id, entries, url, token, tile, progress)
+        })
+    }
 
-            std::thread::spawn(move || runtime.block_on(async { Task::run_upload(client, local_id, entries).await }));
-        }
+    /// Stop the request, the entry's status is left to the caller
+    pub fn cancel(&self) {
+        self.abort.abort();
+    }
 
-        #[cfg(target_arch = "wasm32")]
-        wasm_bindgen_futures::spawn_local(async move { Task::run_upload(client, local_id, entries).await });
+    /// Bytes transferred and the total size when it is known
+    pub fn progress(&self) -> (u64, Option<u64>) {
+        let total = self.progress.total.load(Ordering::Relaxed);
 
-        Self {}
+        (
+            self.progress.transferred.load(Ordering::Relaxed),
+            Some(total).filter(|x| *x > 0),
+        )
     }
 
-    async fn run_download(client: Client, local_id: u32, entries: Arc<RwLock<Vec<Entry>>>, jsonid: String) {
-        if let Some(entry) = entries
-            .write()
-            .unwrap()
-            .iter_mut()
-            .find(|entry| entry.local_id == local_id)
-        {
-            entry.status = EntryStatus::Downloading;
+    fn is_finished(&self) -> bool {
+        self.progress.finished.load(Ordering::Relaxed)
+    }
+
+    /// Remember the entry's task, the previous request of the entry is cancelled
+    fn track(self, tasks: &Tasks, local_id: u32) {
+        let mut tasks = tasks.lock().unwrap();
+
+        forget_finished(&mut tasks);
+        if let Some(previous) = tasks.insert(local_id, self) {
+            previous.cancel();
         }
+    }
 
-        let result = match client
-            .get(format!("https://megingjord-waist.styxheim.ru/get/{}", jsonid))
-            .send()
-            .await
-        {
+    async fn run_new_channel(
+        client: Client,
+        server: String,
+        token: Option<String>,
+        minted: Arc<Mutex<Vec<MintedChannel>>>,
+    ) {
+        let request = with_token(client.post(format!("{}/channel", server)), token.as_deref());
+        let result = match request.send().await {
             Ok(response) => {
                 if response.status() == StatusCode::OK {
                     response
-                        .json::<GeoJson>()
+                        .text()
                         .await
-                        .map_err(|e| format!("json parsing error: {}", e))
+                        .map(|id| id.trim().to_string())
+                        .map_err(|e| format!("{}", e))
                 } else {
-                    Err(format!("server returns code {}", response.status()))
+                    Err(format!("server error code: {}", response.status()))
+                }
+            }
+            Err(err) => Err(format!("{}", err)),
+        };
+
+        minted.lock().unwrap().push(result);
+    }
+
+    async fn run_probe_server(client: Client, name: String, url: String, probed: Arc<Mutex<Vec<ProbedServer>>>) {
+        /* an empty area of the default channel, static hosts answer with a page or 404 */
+        let request = client.get(format!("{}/get/{}?bbox=0,0,0,0", url, DEFAULT_CHANNEL));
+        let serves = match request.send().await {
+            Ok(response) => {
+                let header = |name| {
+                    response
+                        .headers()
+                        .get(name)
+                        .and_then(|value| value.to_str().ok())
+                        .unwrap_or_default()
+                        .to_string()
+                };
+
+                match response.status() {
+                    StatusCode::OK => header(reqwest::header::CONTENT_TYPE).contains("geo+json"),
+                    StatusCode::UNAUTHORIZED => header(reqwest::header::WWW_AUTHENTICATE).starts_with("Bearer"),
+                    _ => false,
                 }
             }
+            Err(err) => {
+                log::warn!("Server {} not probed: {}", url, err);
+                false
+            }
+        };
+
+        probed.lock().unwrap().push(ProbedServer { name, url, serves });
+    }
+
+    async fn run_tile(
+        client: Client,
+        local_id: u32,
+        entries: Arc<RwLock<Vec<Entry>>>,
+        url: String,
+        token: Option<String>,
+        tile: TileId,
+        progress: Arc<TaskProgress>,
+    ) {
+        let result = match with_token(client.get(url), token.as_deref()).send().await {
+            Ok(response) => match response.status() {
+                StatusCode::OK => match read_body(response, &progress).await {
+                    Ok(body) => vector_tiles::decode(&tile, &body).map_err(|e| format!("tile decoding error: {}", e)),
+                    Err(err) => Err(format!("generic error: {}", err)),
+                },
+                status => Err(format!("server returns code {}", status)),
+            },
             Err(err) => Err(format!("generic error: {}", err)),
         };
 
-        if let Some(entry) = entries
+        if let Err(error) = &result {
+            log::error!("Tile {}/{}/{} not received: {}", tile.z, tile.x, tile.y, error);
+        }
+
+        if let Some(tiles) = entries
             .write()
             .unwrap()
             .iter_mut()
             .find(|entry| entry.local_id == local_id)
+            .and_then(|entry| entry.tiles.as_mut())
         {
-            match result {
-                Ok(geojson) => {
-                    entry.status = EntryStatus::Ready;
-                    entry.json = Some(geojson);
+            tiles.set(&tile, result);
+        }
+    }
+
+    /// Request the channel, only changes after the cursor `since` when it is set.
+    /// `Ok(None)` when the copy of `etag` is current
+    async fn request_channel(
+        client: &Client,
+        url: String,
+        token: Option<&str>,
+        etag: Option<String>,
+        since: Option<i64>,
+        progress: &TaskProgress,
+    ) -> Result<Option<ChannelAnswer>, String> {
+        let url = match since {
+            Some(since) => format!("{}?since={}", url, since),
+            None => url,
+        };
+        let mut request = with_token(client.get(url), token);
+
+        if let Some(etag) = etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+
+        match request.send().await {
+            Ok(response) => match response.status() {
+                StatusCode::NOT_MODIFIED => Ok(None),
+                StatusCode::OK => {
+                    let etag = response
+                        .headers()
+                        .get(header::ETAG)
+                        .and_then(|x| x.to_str().ok())
+                        .map(|x| x.to_string());
+
+                    progress
+                        .total
+                        .store(response.content_length().unwrap_or(0), Ordering::Relaxed);
+
+                    match read_body(response, progress).await {
+                        Ok(body) => serde_json::from_slice::<GeoJson>(&body)
+                            .map(|json| Some(ChannelAnswer::new(etag, json)))
+                            .map_err(|e| format!("json parsing error: {}", e)),
+                        Err(err) => Err(format!("generic error: {}", err)),
+                    }
+                }
+                status => Err(format!("server returns code {}", status)),
+            },
+            Err(err) => Err(format!("generic error: {}", err)),
+        }
+    }
+
+    async fn run_download(
+        client: Client,
+        local_id: u32,
+        entries: Arc<RwLock<Vec<Entry>>>,
+        jsonid: String,
+        progress: Arc<TaskProgress>,
+    ) {
+        let Some((server, token, loaded)) = entries
+            .write()
+            .unwrap()
+            .iter_mut()
+            .find(|entry| entry.local_id == local_id)
+            .map(|entry| {
+                entry.status = EntryStatus::Downloading;
+                (entry.server.clone(), entry.token.clone(), entry.json.is_some())
+            })
+        else {
+            return;
+        };
+
+        /* the cached copy is shown until the server answers */
+        let cached = if loaded {
+            None
+        } else {
+            channel_cache::load(&server, &jsonid).await
+        };
+
+        let Some((etag, cursor)) = entries
+            .write()
+            .unwrap()
+            .iter_mut()
+            .find(|entry| entry.local_id == local_id)
+            .map(|entry| {
+                if let Some(cached) = cached {
+                    if entry.json.is_none() {
+                        entry.etag = cached.etag;
+                        entry.cursor = cached.cursor;
+                        entry.set_json(cached.json);
+                    }
                 }
-                Err(error) => {
-                    entry.status = EntryStatus::DownloadError(error);
+                (entry.etag.clone(), entry.cursor.filter(|_| entry.json.is_some()))
+            })
+        else {
+            return;
+        };
+
+        let url = format!("{}/get/{}", server, jsonid);
+        let mut merged = false;
+        let result = match Self::request_channel(&client, url.clone(), token.as_deref(), etag, cursor, &progress).await
+        {
+            Ok(Some(answer)) if answer.changes.is_some() => {
+                merged = entries
+                    .write()
+                    .unwrap()
+                    .iter_mut()
+                    .find(|entry| entry.local_id == local_id)
+                    .is_some_and(|entry| entry.merge_changes(answer));
+
+                if merged {
+                    Ok(None)
+                } else {
+                    /* features expired on the server are not in changes */
+                    log::info!("Changes of {} do not add up, downloading it whole", jsonid);
+                    Self::request_channel(&client, url, token.as_deref(), None, None, &progress).await
                 }
             }
+            result => result,
+        };
+
+        let cached = entries
+            .write()
+            .unwrap()
+            .iter_mut()
+            .find(|entry| entry.local_id == local_id)
+            .and_then(|entry| {
+                let changed = merged || matches!(result, Ok(Some(_)));
+
+                match result {
+                    Ok(Some(answer)) => {
+                        entry.status = EntryStatus::Ready;
+                        entry.etag = answer.etag;
+                        entry.cursor = answer.cursor;
+                        entry.set_json(answer.json);
+                    }
+                    Ok(None) => entry.status = EntryStatus::Ready,
+                    Err(error) if entry.etag.is_some() => entry.status = EntryStatus::Offline(error),
+                    Err(error) => entry.status = EntryStatus::DownloadError(error),
+                }
+
+                if changed {
+                    entry.cached_channel()
+                } else {
+                    None
+                }
+            });
+
+        if let Some(cached) = cached {
+            channel_cache::store(&server, &jsonid, cached).await;
         }
     }
 
-    async fn run_upload(client: Client, local_id: u32, entries: Arc<RwLock<Vec<Entry>>>) {
+    async fn run_upload(client: Client, local_id: u32, entries: Arc<RwLock<Vec<Entry>>>, progress: Arc<TaskProgress>) {
         let json_body = entries
             .write()
             .unwrap()
             .iter_mut()
             .find(|entry| entry.local_id == local_id)
-            .map(|entry| {
+            .and_then(|entry| {
+                let json_body = entry.json.as_ref()?.to_string();
+
                 entry.status = EntryStatus::Uploading;
-                entry.json.as_ref().unwrap().to_string()
+                Some((
+                    entry.server.clone(),
+                    entry.token.clone(),
+                    entry.upload_channel.clone(),
+                    json_body,
+                ))
             });
 
-        let status = if let Some(json_body) = json_body {
-            let response = client
-                .post("https://megingjord-waist.styxheim.ru/new")
+        let status = if let Some((server, token, channel, json_body)) = json_body {
+            let response = with_token(client.post(format!("{}/new/{}", server, channel)), token.as_deref())
                 .header(header::CONTENT_TYPE, "application/geo+json")
-                .body(json_body)
+                .header(header::ACCEPT, "application/json")
+                .body(upload_body(json_body.into_bytes(), progress))
                 .send()
                 .await;
 
             match response {
                 Ok(response) => {
                     if response.status() == StatusCode::OK {
-                        response.text().await.map_err(|e| format!("{}", e))
+                        response.text().await.map_err(|e| format!("{}", e)).map(|text| {
+                            /* servers without features' ids answer with the bare channel id */
+                            serde_json::from_str::<UploadAnswer>(&text).unwrap_or(UploadAnswer {
+                                channel: text.trim().to_string(),
+                                ids: Vec::new(),
+                            })
+                        })
                     } else {
                         Err(format!("server error code: {}", response.status()))
                     }
@@ -125,18 +484,41 @@ impl Task {
                 Err(err) => Err(format!("{}", err)),
             }
         } else {
+            log::error!("Entry {} not uploaded: nothing to upload", local_id);
             Err("Nothing to upload: body is empty".to_string())
         };
 
         match status {
-            Ok(identifier) => {
+            Ok(UploadAnswer {
+                channel: identifier,
+                ids,
+            }) => {
                 let mut entries = entries.write().unwrap();
                 let entry_pos = entries.iter().position(|entry| entry.local_id == local_id);
 
                 if let Some(entry_pos) = entry_pos {
-                    if let Some(similar_entry_pos) = entries.iter().position(|entry| entry.id == identifier) {
-                        if let Some(mut json) = entries.swap_remove(entry_pos).json {
-                            entries[similar_entry_pos].append(&mut json);
+                    entries[entry_pos].set_feature_ids(&ids);
+
+                    let server = entries[entry_pos].server.clone();
+                    /* tiled layers do not draw merged data, they get the features with new tiles */
+                    let similar_entry_pos = entries.iter().position(|entry| {
+                        entry.id == identifier
+                            && entry.server == server
+                            && entry.file_name.is_none()
+                            && entry.tiles.is_none()
+                    });
+
+                    if let Some(similar_entry_pos) = similar_entry_pos {
+                        let entry = entries.remove(entry_pos);
+                        let similar_entry_pos = if similar_entry_pos > entry_pos {
+                            similar_entry_pos - 1
+                        } else {
+                            similar_entry_pos
+                        };
+
+                        /* features got the server's ids, the ones pushed by the subscription replace them */
+                        if let Some(json) = entry.json {
+                            entries[similar_entry_pos].append(json);
                         }
                     } else {
                         entries[entry_pos].status = EntryStatus::Ready;
@@ -158,6 +540,131 @@ impl Task {
     }
 }
 
+impl Task {
+    /// Merge live updates of the channel into the entry
+    fn apply_subscription_event(
+        spawner: &Spawner,
+        client: &Client,
+        entries: &Arc<RwLock<Vec<Entry>>>,
+        tasks: &Tasks,
+        local_id: u32,
+        jsonid: &str,
+        event: SubscriptionEvent,
+    ) {
+        let mut resync = false;
+
+        if let Some(entry) = entries
+            .write()
+            .unwrap()
+            .iter_mut()
+            .find(|entry| entry.local_id == local_id)
+        {
+            match event {
+                SubscriptionEvent::Connected { reconnect } => {
+                    entry.live = true;
+                    resync = reconnect;
+                }
+                SubscriptionEvent::Disconnected => entry.live = false,
+                /* changed features may be in any tile */
+                SubscriptionEvent::Features(_) if entry.tiles.is_some() => resync = true,
+                SubscriptionEvent::Features(data) => match data.parse::<GeoJson>() {
+                    Ok(mut json) => {
+                        if entry.axis_order == AxisOrder::LatLon {
+                            swap_geojson_axes(&mut json);
+                        }
+                        entry.append(json);
+                    }
+                    Err(err) => log::error!("Update of {} is not valid GeoJSON: {}", jsonid, err),
+                },
+                SubscriptionEvent::Deleted(_) if entry.tiles.is_some() => resync = true,
+                SubscriptionEvent::Deleted(data) => match data.parse::<geojson::JsonValue>() {
+                    Ok(geojson::JsonValue::Array(ids)) => entry.remove(&feature_ids(ids)),
+                    _ => log::error!("Deletion in {} is not a list of ids: {}", jsonid, data),
+                },
+                SubscriptionEvent::Resync => resync = true,
+            }
+
+            /* shown tiles are requested again instead of the whole channel */
+            if let Some(tiles) = entry.tiles.as_mut().filter(|_| resync) {
+                tiles.invalidate();
+                resync = false;
+            }
+        }
+
+        if resync {
+            log::info!("Updates of {} may be lost, downloading again", jsonid);
+            Task::download(spawner, client.clone(), local_id, entries, jsonid.to_string()).track(tasks, local_id);
+        }
+    }
+}
+
+/// Order of axes in the entry's positions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+enum AxisOrder {
+    /// RFC 7946: longitude, latitude
+    #[default]
+    LonLat,
+    /// Legacy data written by old clients: latitude, longitude
+    LatLon,
+}
+
+fn swap_position_axes(position: &mut geojson::Position) {
+    if position.len() >= 2 {
+        position.swap(0, 1);
+    }
+}
+
+fn swap_bbox_axes(bbox: &mut geojson::Bbox) {
+    /* [x1, y1, x2, y2] or [x1, y1, z1, x2, y2, z2] */
+    let dimensions = bbox.len() / 2;
+
+    if dimensions >= 2 {
+        bbox.swap(0, 1);
+        bbox.swap(dimensions, dimensions + 1);
+    }
+}
+
+fn swap_geometry_axes(geometry: &mut geojson::Geometry) {
+    if let Some(bbox) = &mut geometry.bbox {
+        swap_bbox_axes(bbox);
+    }
+
+    match &mut geometry.value {
+        geojson::Value::Point(point) => swap_position_axes(point),
+        geojson::Value::MultiPoint(points) | geojson::Value::LineString(points) => {
+            points.iter_mut().for_each(swap_position_axes)
+        }
+        geojson::Value::MultiLineString(lines) | geojson::Value::Polygon(lines) => {
+            lines.iter_mut().flatten().for_each(swap_position_axes)
+        }
+        geojson::Value::MultiPolygon(polygons) => polygons.iter_mut().flatten().flatten().for_each(swap_position_axes),
+        geojson::Value::GeometryCollection(geometries) => geometries.iter_mut().for_each(swap_geometry_axes),
+    }
+}
+
+fn swap_feature_axes(feature: &mut geojson::Feature) {
+    if let Some(bbox) = &mut feature.bbox {
+        swap_bbox_axes(bbox);
+    }
+    if let Some(geometry) = &mut feature.geometry {
+        swap_geometry_axes(geometry);
+    }
+}
+
+/// Convert positions between lat/lon and lon/lat orders
+fn swap_geojson_axes(json: &mut GeoJson) {
+    match json {
+        GeoJson::Geometry(geometry) => swap_geometry_axes(geometry),
+        GeoJson::Feature(feature) => swap_feature_axes(feature),
+        GeoJson::FeatureCollection(fc) => {
+            if let Some(bbox) = &mut fc.bbox {
+                swap_bbox_axes(bbox);
+            }
+            fc.features.iter_mut().for_each(swap_feature_axes);
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 enum EntryStatus {
     #[default]
@@ -165,232 +672,2328 @@ enum EntryStatus {
     Ready,
     Downloading,
     DownloadError(String),
+    /// Server is not reachable, the cached copy is shown
+    Offline(String),
     Uploading,
     UploadError(String),
+    /// Request is stopped by the user
+    Cancelled,
+}
+
+impl EntryStatus {
+    fn icon(&self) -> &'static str {
+        match self {
+            Self::Wait => "⏳",
+            Self::Ready => "✔",
+            Self::Downloading => "⬇",
+            Self::Offline(_) => "💾",
+            Self::Uploading => "⬆",
+            Self::Cancelled => "⏹",
+            Self::DownloadError(_) | Self::UploadError(_) => "⚠",
+        }
+    }
+
+    fn is_error(&self) -> bool {
+        matches!(self, Self::DownloadError(_) | Self::UploadError(_))
+    }
+
+    fn is_in_progress(&self) -> bool {
+        matches!(self, Self::Downloading | Self::Uploading)
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::Wait => "Waiting".to_string(),
+            Self::Ready => "Ready".to_string(),
+            Self::Downloading => "Downloading".to_string(),
+            Self::Uploading => "Uploading".to_string(),
+            Self::DownloadError(error) => format!("Download failed: {}", error),
+            Self::Offline(error) => format!("Cached copy, server is not reachable: {}", error),
+            Self::UploadError(error) => format!("Upload failed: {}", error),
+            Self::Cancelled => "Cancelled".to_string(),
+        }
+    }
 }
 
+/// Spatial index of the entry's features, data is the feature's position in the collection
+type FeatureIndex = RTree<GeomWithData<Rectangle<[f64; 2]>, usize>>;
+
 struct Entry {
     local_id: u32,
     id: String,
+    /// Base url of the waist server the entry belongs to
+    server: String,
+    /// Token of the server's profile, it is not saved with the layer
+    token: Option<String>,
+    /// Channel the local data is uploaded to
+    upload_channel: String,
     json: Option<GeoJson>,
     visible: bool,
     status: EntryStatus,
+    /// Axis order of the received data, positions in `json` are always stored as lon/lat
+    axis_order: AxisOrder,
+    index: FeatureIndex,
+    show_bbox: bool,
+    /// Failed upload attempts since the last success or manual retry
+    upload_attempts: u32,
+    /// Time of the next automatic upload attempt, in seconds of `egui::InputState::time`
+    retry_at: Option<f64>,
+    /// Name of the imported local file, such entries are not uploaded
+    file_name: Option<String>,
+    /// Path of the imported local file to import it again at start, browsers do not tell it
+    file_path: Option<String>,
+    /// Name given by the user, empty to show the id or the file's name
+    name: String,
+    opacity: f32,
+    /// Color replacing colors of all features
+    color: Option<Color32>,
+    /// Subscription to the channel's updates is connected
+    live: bool,
+    /// Entity tag of the downloaded channel, `None` when there is nothing to revalidate
+    etag: Option<String>,
+    /// Cursor of the downloaded channel to request only later changes, `None` for servers without cursors
+    cursor: Option<i64>,
+    /// Received vector tiles of the channel shown by tiles instead of the whole download
+    tiles: Option<TileCache>,
+}
+
+/// Request from the entry's controls to the dispatcher
+enum EntryAction {
+    ZoomTo,
+    RetryUpload,
+    /// Download the channel again
+    Reload,
+    Cancel,
+    Delete,
+    SaveAs(geojson_file::FileFormat),
 }
 
 impl Entry {
-    fn new_with_id(local_id: u32, id: String) -> Self {
+    fn new_with_id(local_id: u32, id: String, server: String) -> Self {
         Self {
             local_id,
             id,
+            server,
+            token: None,
+            upload_channel: String::new(),
             json: None,
             visible: true,
             status: Default::default(),
+            axis_order: Default::default(),
+            index: Default::default(),
+            show_bbox: false,
+            upload_attempts: 0,
+            retry_at: None,
+            file_name: None,
+            file_path: None,
+            name: String::new(),
+            opacity: 1.0,
+            color: None,
+            live: false,
+            etag: None,
+            cursor: None,
+            tiles: None,
         }
     }
 
-    fn new_with_json(local_id: u32, json: GeoJson) -> Self {
-        Self {
+    fn new_with_json(local_id: u32, json: GeoJson, server: String, upload_channel: String) -> Self {
+        let mut entry = Self {
             local_id,
             id: "".to_string(),
+            server,
+            token: None,
+            upload_channel,
             json: Some(json.clone()),
             visible: true,
             status: Default::default(),
-        }
+            axis_order: Default::default(),
+            index: Default::default(),
+            show_bbox: false,
+            upload_attempts: 0,
+            retry_at: None,
+            file_name: None,
+            file_path: None,
+            name: String::new(),
+            opacity: 1.0,
+            color: None,
+            live: false,
+            etag: None,
+            cursor: None,
+            tiles: None,
+        };
+
+        entry.reindex();
+        entry
     }
 
-    pub fn show_ui(&mut self, ui: &mut Ui) {
-        ui.checkbox(
-            &mut self.visible,
-            RichText::new(format!("{}: {:?}", self.id, self.status)).heading(),
-        );
+    /// Channel shown by tiles of the visible area, nothing is downloaded until it is drawn
+    fn new_with_tiles(local_id: u32, id: String, server: String) -> Self {
+        let mut entry = Self::new_with_id(local_id, id, server);
+
+        entry.tiles = Some(Default::default());
+        entry.status = EntryStatus::Ready;
+        entry
     }
 
-    fn append(&mut self, other_geojson: &mut GeoJson) {
-        if let GeoJson::FeatureCollection(fc) = other_geojson {
-            if let Some(GeoJson::FeatureCollection(self_feature_collection)) = &mut self.json {
-                self_feature_collection.features.append(&mut fc.features);
-                if let (Some(self_bbox), Some(other_bbox)) = (&self_feature_collection.bbox, &fc.bbox) {
-                    let mut new_bbox = self_bbox.clone();
+    fn new_with_file(local_id: u32, file_name: String, json: GeoJson) -> Self {
+        let mut entry = Self::new_with_json(local_id, json, "".to_string(), "".to_string());
 
-                    for idx in 0..3 {
-                        if self_bbox[idx] < other_bbox[idx] {
-                            new_bbox[idx] = other_bbox[idx];
-                        }
-                    }
+        entry.file_name = Some(file_name);
+        entry.status = EntryStatus::Ready;
+        entry
+    }
 
-                    self_feature_collection.bbox = Some(new_bbox);
-                }
-            } else {
-                let mut new_json = geojson::FeatureCollection {
-                    features: Vec::new(),
-                    foreign_members: None,
-                    bbox: fc.bbox.clone(),
-                };
+    /// Local data not accepted by the server yet
+    fn is_pending_upload(&self) -> bool {
+        self.file_name.is_none() && self.id.is_empty() && self.json.is_some()
+    }
 
-                new_json.features.append(&mut fc.features);
-            }
+    fn title(&self) -> &str {
+        if !self.name.is_empty() {
+            &self.name
+        } else {
+            self.file_name.as_deref().unwrap_or(&self.id)
         }
     }
-}
 
-pub struct GeoJsonDispatcher {
-    entries: Arc<RwLock<Vec<Entry>>>,
-    client: Client,
-    id_generator: u32,
-}
+    fn feature_count(&self) -> usize {
+        if let Some(tiles) = &self.tiles {
+            return tiles.feature_count();
+        }
 
-impl GeoJsonDispatcher {
-    fn next_id(&mut self) -> u32 {
-        self.id_generator += 1;
-        self.id_generator
+        match &self.json {
+            Some(GeoJson::FeatureCollection(fc)) => fc.features.len(),
+            Some(_) => 1,
+            None => 0,
+        }
     }
 
-    pub fn new() -> Self {
-        Self {
-            entries: Default::default(),
-            client: Default::default(),
-            id_generator: 1,
-        }
+    /// Style of the feature with the layer's settings applied
+    fn style(&self, properties: Option<&geojson::JsonObject>) -> FeatureStyle {
+        FeatureStyle::from_properties(properties).with_layer(self.color, self.opacity)
     }
 
-    pub fn download(&mut self, id: String) {
-        let local_id = self.next_id();
+    /// Rebuild spatial index, must be called after every change of `json`
+    fn reindex(&mut self) {
+        let index_item = |idx: usize, geometry: Option<&geojson::Geometry>| {
+            geometry
+                .and_then(|geometry| geojson_geometry::envelope(&geometry.value))
+                .map(|envelope| GeomWithData::new(Rectangle::from_aabb(envelope), idx))
+        };
 
-        self.entries
-            .write()
-            .unwrap()
-            .push(Entry::new_with_id(local_id, id.clone()));
-        Task::download(self.client.clone(), local_id, &self.entries, id);
+        let items = match &self.json {
+            Some(GeoJson::Geometry(geometry)) => index_item(0, Some(geometry)).into_iter().collect(),
+            Some(GeoJson::Feature(feature)) => index_item(0, feature.geometry.as_ref()).into_iter().collect(),
+            Some(GeoJson::FeatureCollection(fc)) => fc
+                .features
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, feature)| index_item(idx, feature.geometry.as_ref()))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        self.index = RTree::bulk_load(items);
     }
 
-    pub fn upload_json_array(&mut self, jsons: &mut Vec<geojson::GeoJson>) {
-        while let Some(json) = jsons.pop() {
-            let local_id = self.next_id();
-            self.entries.write().unwrap().push(Entry::new_with_json(local_id, json));
-            Task::upload(self.client.clone(), local_id, &self.entries);
-        }
+    /// Positions in the collection of features intersecting with `area`, in the drawing order
+    fn features_in(&self, area: &AABB<[f64; 2]>) -> Vec<usize> {
+        let mut indexes: Vec<usize> = self
+            .index
+            .locate_in_envelope_intersecting(area)
+            .map(|item| item.data)
+            .collect();
+
+        indexes.sort_unstable();
+        indexes
     }
-}
 
-impl Default for GeoJsonDispatcher {
-    fn default() -> Self {
-        Self::new()
+    /// Extent from the data's bbox or computed from features when bbox is not set
+    fn extent(&self) -> Option<AABB<[f64; 2]>> {
+        let bbox = match self.json.as_ref()? {
+            GeoJson::Geometry(geometry) => geometry.bbox.as_ref(),
+            GeoJson::Feature(feature) => feature.bbox.as_ref(),
+            GeoJson::FeatureCollection(fc) => fc.bbox.as_ref(),
+        };
+
+        bbox.and_then(geojson_geometry::bbox_to_envelope).or_else(|| {
+            if self.index.size() > 0 {
+                Some(self.index.root().envelope())
+            } else {
+                None
+            }
+        })
     }
-}
 
-fn pair_to_screen_coords(point_pair: &[f64], projector: &Projector) -> egui::Pos2 {
-    let x = point_pair[0];
-    let y = point_pair[1];
+    /// Feature at position `idx` of the collection, bare geometry is wrapped into a feature
+    fn feature(&self, idx: usize) -> Option<geojson::Feature> {
+        match self.json.as_ref()? {
+            GeoJson::Geometry(geometry) if idx == 0 => Some(geojson::Feature::from(geometry.clone())),
+            GeoJson::Feature(feature) if idx == 0 => Some(feature.clone()),
+            GeoJson::FeatureCollection(fc) => fc.features.get(idx).cloned(),
+            _ => None,
+        }
+    }
 
-    projector.project(walkers::Position::from_lat_lon(x, y)).to_pos2()
-}
+    /// The selected feature, features of tiles are clipped by their tiles
+    fn selected_feature(&self, selected: &SelectedFeature) -> Option<geojson::Feature> {
+        match (&selected.tile, &self.tiles) {
+            (Some(tile), Some(tiles)) => tiles.get(tile)?.features.get(selected.idx).cloned(),
+            (None, None) => self.feature(selected.idx),
+            _ => None,
+        }
+    }
+
+    /// Set received data, converting legacy lat/lon positions if needed
+    fn set_json(&mut self, mut json: GeoJson) {
+        if self.axis_order == AxisOrder::LatLon {
+            swap_geojson_axes(&mut json);
+        }
+        self.json = Some(json);
+        self.reindex();
+    }
+
+    fn set_axis_order(&mut self, axis_order: AxisOrder) {
+        if self.axis_order != axis_order {
+            if let Some(json) = &mut self.json {
+                swap_geojson_axes(json);
+            }
+            self.axis_order = axis_order;
+            self.reindex();
+        }
+    }
+
+    fn show_ui_settings(&mut self, ui: &mut Ui, action: &mut Option<EntryAction>) {
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            ui.add(
+                TextEdit::singleline(&mut self.name)
+                    .hint_text(self.file_name.as_deref().unwrap_or(&self.id))
+                    .desired_width(120.0),
+            );
+        });
+
+        ui.add(Slider::new(&mut self.opacity, 0.0..=1.0).text("Opacity"));
+
+        ui.horizontal(|ui| {
+            let mut override_color = self.color.is_some();
+
+            if ui.checkbox(&mut override_color, "Color").changed() {
+                self.color = if override_color { Some(Color32::RED) } else { None };
+            }
+
+            if let Some(color) = &mut self.color {
+                let mut rgb = [color.r(), color.g(), color.b()];
+
+                if ui.color_edit_button_srgb(&mut rgb).changed() {
+                    *color = Color32::from_rgb(rgb[0], rgb[1], rgb[2]);
+                }
+            }
+        });
+
+        /* tiles are always in lon/lat and have no extent of the whole layer */
+        if self.tiles.is_none() {
+            let mut legacy = self.axis_order == AxisOrder::LatLon;
+
+            if ui
+                .checkbox(&mut legacy, "lat/lon")
+                .on_hover_text("Data uses legacy latitude, longitude axis order")
+                .changed()
+            {
+                self.set_axis_order(if legacy { AxisOrder::LatLon } else { AxisOrder::LonLat });
+            }
+
+            ui.checkbox(&mut self.show_bbox, "bbox")
+                .on_hover_text("Show the layer's boundary box");
+        }
+
+        if self.json.is_some() {
+            ui.menu_button("💾 Save as…", |ui| {
+                for format in geojson_file::FileFormat::ALL {
+                    if ui.button(format.name()).clicked() {
+                        *action = Some(EntryAction::SaveAs(format));
+                        ui.close_menu();
+                    }
+                }
+            });
+        }
+    }
+
+    /// Row of the layer list, `progress` is bytes transferred and the total size of the running request.
+    /// Returns the drag handle's response with the requested action
+    pub fn show_ui(&mut self, ui: &mut Ui, progress: Option<(u64, Option<u64>)>) -> (Response, Option<EntryAction>) {
+        let mut action = None;
+
+        let handle = ui
+            .add(Label::new("☰").sense(Sense::drag()))
+            .on_hover_text("Drag to change the drawing order");
+
+        ui.checkbox(&mut self.visible, "");
+
+        let icon = RichText::new(self.status.icon());
+        let icon = if self.status.is_error() {
+            icon.color(ui.visuals().error_fg_color)
+        } else {
+            icon
+        };
+
+        ui.label(icon).on_hover_text(self.status.describe());
+        if self.live {
+            ui.label("📡").on_hover_text("Live updates");
+        }
+        ui.label(RichText::new(self.title()).heading())
+            .on_hover_text(format!("{}/get/{}", self.server, self.id));
+        if self.tiles.is_some() {
+            ui.label("🗺").on_hover_text("Shown by tiles of the visible area");
+        }
+        ui.label(format!("({})", self.feature_count()))
+            .on_hover_text("Number of features");
+
+        if self.index.size() > 0 && ui.button("🔍").on_hover_text("Zoom to layer").clicked() {
+            action = Some(EntryAction::ZoomTo);
+        }
+
+        ui.menu_button("⚙", |ui| self.show_ui_settings(ui, &mut action))
+            .response
+            .on_hover_text("Layer settings");
+
+        if let Some((transferred, total)) = progress.filter(|_| self.status.is_in_progress()) {
+            let progress_bar = match total {
+                Some(total) => ProgressBar::new(transferred as f32 / total as f32).text(format!(
+                    "{} / {}",
+                    format_size(transferred),
+                    format_size(total)
+                )),
+                None => ProgressBar::new(0.0).animate(true).text(format_size(transferred)),
+            };
+
+            ui.add(progress_bar.desired_width(120.0));
+            if ui.button("✖").on_hover_text("Cancel").clicked() {
+                action = Some(EntryAction::Cancel);
+            }
+        }
+
+        let retry = match self.status {
+            EntryStatus::UploadError(_) => Some(("Retry upload now", EntryAction::RetryUpload)),
+            EntryStatus::Cancelled if self.is_pending_upload() => Some(("Upload again", EntryAction::RetryUpload)),
+            EntryStatus::Cancelled => Some(("Download again", EntryAction::Reload)),
+            EntryStatus::DownloadError(_) if self.tiles.is_some() => {
+                Some(("Download tiles again", EntryAction::Reload))
+            }
+            _ => None,
+        };
+
+        if let Some((hint, retry)) = retry {
+            if ui.button("⟳").on_hover_text(hint).clicked() {
+                action = Some(retry);
+            }
+        }
+
+        let delete_hint = if self.is_pending_upload() {
+            "Discard local data"
+        } else {
+            "Delete layer"
+        };
+
+        if ui.button("🗑").on_hover_text(delete_hint).clicked() {
+            action = Some(EntryAction::Delete);
+        }
+
+        (handle, action)
+    }
+
+    /// Status of the tiled entry from its tiles' requests, must be called every frame
+    fn update_tiles_status(&mut self) {
+        let Some(tiles) = &self.tiles else {
+            return;
+        };
+
+        self.status = if tiles.is_loading() {
+            EntryStatus::Downloading
+        } else if let Some(error) = tiles.error() {
+            EntryStatus::DownloadError(error.to_string())
+        } else {
+            EntryStatus::Ready
+        };
+    }
+
+    /// Merge features of `other`, features with the same id are replaced
+    fn append(&mut self, other: GeoJson) {
+        let other = into_collection(other);
+        let mut collection = match self.json.take() {
+            Some(json) => into_collection(json),
+            None => geojson::FeatureCollection {
+                bbox: None,
+                features: Vec::new(),
+                foreign_members: None,
+            },
+        };
+
+        collection.bbox = if collection.features.is_empty() {
+            other.bbox
+        } else {
+            match (&collection.bbox, &other.bbox) {
+                (Some(bbox), Some(other_bbox)) => geojson_geometry::bbox_union(bbox, other_bbox),
+                /* unknown extent of a part, computed from features when needed */
+                _ => None,
+            }
+        };
+
+        for feature in other.features {
+            let same = feature
+                .id
+                .as_ref()
+                .and_then(|id| collection.features.iter().position(|x| x.id.as_ref() == Some(id)));
+
+            match same {
+                Some(idx) => collection.features[idx] = feature,
+                None => collection.features.push(feature),
+            }
+        }
+
+        self.json = Some(GeoJson::FeatureCollection(collection));
+        self.reindex();
+    }
+
+    /// Merge changes of the channel made after the entry's cursor, returns false when they do not add up
+    /// to the channel's features: the channel must be downloaded whole then
+    fn merge_changes(&mut self, answer: ChannelAnswer) -> bool {
+        let Some((deleted, count)) = answer.changes else {
+            return false;
+        };
+
+        if self.json.is_none() {
+            return false;
+        }
+
+        let mut json = answer.json;
+
+        if self.axis_order == AxisOrder::LatLon {
+            swap_geojson_axes(&mut json);
+        }
+        self.remove(&deleted);
+        self.append(json);
+
+        if self.feature_count() != count {
+            return false;
+        }
+
+        self.etag = answer.etag;
+        self.cursor = answer.cursor;
+        true
+    }
+
+    /// Copy of the downloaded channel for the cache with positions in the order of the server
+    fn cached_channel(&self) -> Option<channel_cache::CachedChannel> {
+        let mut json = self.json.clone()?;
+
+        if self.axis_order == AxisOrder::LatLon {
+            swap_geojson_axes(&mut json);
+        }
+
+        Some(channel_cache::CachedChannel {
+            etag: self.etag.clone(),
+            cursor: self.cursor,
+            json,
+        })
+    }
+
+    /// Set ids given by the server to features in their order
+    fn set_feature_ids(&mut self, ids: &[Option<i64>]) {
+        if let Some(GeoJson::FeatureCollection(fc)) = &mut self.json {
+            for (feature, id) in fc.features.iter_mut().zip(ids) {
+                if let Some(id) = id {
+                    feature.id = Some(geojson::feature::Id::Number((*id).into()));
+                }
+            }
+        }
+    }
+
+    /// Remove features with the ids, the rest keep their order
+    fn remove(&mut self, ids: &[geojson::feature::Id]) {
+        let removed = |feature: &geojson::Feature| feature.id.as_ref().is_some_and(|id| ids.contains(id));
+
+        match &mut self.json {
+            Some(GeoJson::FeatureCollection(fc)) => fc.features.retain(|feature| !removed(feature)),
+            Some(GeoJson::Feature(feature)) if removed(feature) => self.json = None,
+            _ => return,
+        }
+        self.reindex();
+    }
+}
+
+/// Data as a collection of features, bare geometry is wrapped into a feature
+fn into_collection(json: GeoJson) -> geojson::FeatureCollection {
+    match json {
+        GeoJson::Geometry(geometry) => geojson::FeatureCollection {
+            bbox: geometry.bbox.clone(),
+            features: vec![geojson::Feature::from(geometry)],
+            foreign_members: None,
+        },
+        GeoJson::Feature(feature) => geojson::FeatureCollection {
+            bbox: feature.bbox.clone(),
+            features: vec![feature],
+            foreign_members: None,
+        },
+        GeoJson::FeatureCollection(fc) => fc,
+    }
+}
+
+/// Features' ids of the JSON list, other values are skipped
+fn feature_ids(ids: Vec<geojson::JsonValue>) -> Vec<geojson::feature::Id> {
+    ids.into_iter()
+        .filter_map(|id| match id {
+            geojson::JsonValue::String(id) => Some(geojson::feature::Id::String(id)),
+            geojson::JsonValue::Number(id) => Some(geojson::feature::Id::Number(id)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Foreign members of waist's answers: the cursor of the answer,
+/// ids of features deleted after the requested cursor and the number of the channel's features
+const MEMBER_CURSOR: &str = "cursor";
+const MEMBER_DELETED: &str = "deleted";
+const MEMBER_COUNT: &str = "count";
+
+/// Channel received from the server
+struct ChannelAnswer {
+    etag: Option<String>,
+    /// Cursor to request later changes, `None` for servers without cursors
+    cursor: Option<i64>,
+    json: GeoJson,
+    /// The answer has only changes after the requested cursor:
+    /// ids of deleted features and the number of the channel's features
+    changes: Option<(Vec<geojson::feature::Id>, usize)>,
+}
+
+impl ChannelAnswer {
+    /// Answer with waist's members taken out of the collection
+    fn new(etag: Option<String>, mut json: GeoJson) -> Self {
+        let mut cursor = None;
+        let mut changes = None;
+
+        if let GeoJson::FeatureCollection(fc) = &mut json {
+            if let Some(members) = &mut fc.foreign_members {
+                cursor = members.remove(MEMBER_CURSOR).and_then(|x| x.as_i64());
+
+                let deleted = members.remove(MEMBER_DELETED);
+                let count = members.remove(MEMBER_COUNT).and_then(|x| x.as_u64());
+
+                if let (Some(geojson::JsonValue::Array(deleted)), Some(count)) = (deleted, count) {
+                    changes = Some((feature_ids(deleted), count as usize));
+                }
+                if members.is_empty() {
+                    fc.foreign_members = None;
+                }
+            }
+        }
+
+        Self {
+            etag,
+            cursor,
+            json,
+            changes,
+        }
+    }
+}
+
+/// Feature chosen by click on the map
+struct SelectedFeature {
+    local_id: u32,
+    /// Received tile of the tiled entry with the feature, `idx` is the position in the tile's features
+    tile: Option<TileId>,
+    idx: usize,
+}
+
+/// Answer of the server to the upload
+#[derive(Deserialize)]
+struct UploadAnswer {
+    channel: String,
+    /// Stable ids of the uploaded features in their order, `None` for features which are not stored
+    ids: Vec<Option<i64>>,
+}
+
+/// Upload kept in config until the server accepts it
+#[derive(Serialize, Deserialize)]
+struct PendingUpload {
+    server: String,
+    /// Uploads saved before channels go to the default one
+    #[serde(default = "default_channel")]
+    channel: String,
+    json: GeoJson,
+}
+
+fn default_channel() -> String {
+    DEFAULT_CHANNEL.to_string()
+}
+
+/// Progress bars of running requests are updated with this interval
+const PROGRESS_REPAINT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Delay before the first automatic retry of a failed upload, doubled after each attempt
+const UPLOAD_RETRY_DELAY: f64 = 5.0;
+const UPLOAD_RETRY_DELAY_MAX: f64 = 300.0;
+
+fn upload_retry_delay(attempts: u32) -> f64 {
+    (UPLOAD_RETRY_DELAY * 2f64.powi(attempts.min(16) as i32)).min(UPLOAD_RETRY_DELAY_MAX)
+}
+
+pub const DEFAULT_SERVER_NAME: &str = "styxheim";
+pub const DEFAULT_SERVER_URL: &str = "https://megingjord-waist.styxheim.ru";
+
+/// Layer list entry kept in config
+#[derive(Serialize, Deserialize)]
+struct LayerSettings {
+    id: String,
+    server: String,
+    /// Imported files are read again from `path`, other layers are downloaded again
+    file_name: Option<String>,
+    #[serde(default)]
+    path: Option<String>,
+    /// Data of imported files in settings saved before paths, it is restored once and not saved again
+    #[serde(default, skip_serializing)]
+    json: Option<GeoJson>,
+    name: String,
+    visible: bool,
+    opacity: f32,
+    color: Option<Color>,
+    axis_order: AxisOrder,
+    show_bbox: bool,
+    /// Channel is shown by vector tiles, absent in settings saved before tiles
+    #[serde(default)]
+    tiled: bool,
+}
+
+/// Local file picked in the file dialog
+struct OpenedFile {
+    name: String,
+    /// Content of the file or the reading error
+    bytes: Result<Vec<u8>, String>,
+    /// Path to import the file again at start, unknown in browsers
+    path: Option<String>,
+}
+
+/// Channel id issued by the server or the error message
+type MintedChannel = Result<String, String>;
+
+/// Answer of the server probe, the profile is kept only when the url `serves` waist
+struct ProbedServer {
+    name: String,
+    url: String,
+    serves: bool,
+}
+
+/// Channel opened at the first start
+pub const DEFAULT_CHANNEL: &str = "world";
+
+pub struct GeoJsonDispatcher {
+    entries: Arc<RwLock<Vec<Entry>>>,
+    client: Client,
+    id_generator: u32,
+    selected: Option<SelectedFeature>,
+    servers: ServerProfiles,
+    /// Name of the selected server profile
+    server: String,
+    /// Inputs of the new server profile form
+    new_server_name: String,
+    new_server_url: String,
+    server_error: Option<String>,
+    /// Profiles added once their servers are probed
+    probed_servers: Arc<Mutex<Vec<ProbedServer>>>,
+    /// Input of the selected profile's token
+    token_input: String,
+    /// Files picked in the browser's file dialog, read asynchronously
+    opened_files: Arc<Mutex<Vec<OpenedFile>>>,
+    import_error: Option<String>,
+    /// Layer moved in the list by its drag handle
+    dragged: Option<u32>,
+    /// Live updates of downloaded layers by entries' local ids
+    subscriptions: HashMap<u32, Subscription>,
+    channels: RecentChannels,
+    /// Input of the open channel form
+    channel_input: String,
+    /// Open channels by tiles of the visible area instead of downloading them whole
+    channel_tiled: bool,
+    /// Channels created by the server, opened on the next frame
+    minted_channels: Arc<Mutex<Vec<MintedChannel>>>,
+    channel_error: Option<String>,
+    /// Requests in flight by entries' local ids
+    tasks: Tasks,
+    network: Network,
+}
+
+impl GeoJsonDispatcher {
+    fn next_id(&mut self) -> u32 {
+        self.id_generator += 1;
+        self.id_generator
+    }
+
+    pub fn new() -> Self {
+        Self {
+            entries: Default::default(),
+            client: Default::default(),
+            id_generator: 1,
+            selected: None,
+            servers: Self::default_servers(),
+            server: DEFAULT_SERVER_NAME.to_string(),
+            new_server_name: Default::default(),
+            new_server_url: Default::default(),
+            server_error: None,
+            probed_servers: Default::default(),
+            token_input: Default::default(),
+            opened_files: Default::default(),
+            import_error: None,
+            dragged: None,
+            subscriptions: HashMap::new(),
+            channels: Default::default(),
+            channel_input: Default::default(),
+            channel_tiled: false,
+            minted_channels: Default::default(),
+            channel_error: None,
+            tasks: Default::default(),
+            network: Network::new(),
+        }
+    }
+
+    fn default_servers() -> ServerProfiles {
+        let mut servers = ServerProfiles::default();
+
+        servers.insert(DEFAULT_SERVER_NAME, DEFAULT_SERVER_URL).unwrap();
+        servers
+    }
+
+    /// Replace server profiles and select `server`, default profile is used when `servers` is empty
+    pub fn set_servers(&mut self, servers: ServerProfiles, server: Option<String>) {
+        self.servers = if servers.is_empty() {
+            Self::default_servers()
+        } else {
+            servers
+        };
+
+        self.server = server
+            .filter(|name| self.servers.get(name).is_some())
+            .or_else(|| self.servers.iter().next().map(|profile| profile.name.clone()))
+            .unwrap_or_default();
+    }
+
+    pub fn servers(&self) -> &ServerProfiles {
+        &self.servers
+    }
+
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    /// Add the profile `name` when `url` serves waist, the stored profile with the same url is removed otherwise
+    pub fn probe_server(&mut self, name: &str, url: &str) {
+        /* short request, it is not tracked */
+        Task::probe_server(
+            self.network.spawner(),
+            self.client.clone(),
+            name.to_string(),
+            url.trim_end_matches('/').to_string(),
+            &self.probed_servers,
+        );
+    }
+
+    fn add_probed_servers(&mut self) {
+        let probed = std::mem::take(&mut *self.probed_servers.lock().unwrap());
+
+        for ProbedServer { name, url, serves } in probed {
+            if serves {
+                log::info!("Server {} is added as '{}'", url, name);
+                if let Err(err) = self.servers.insert(&name, &url) {
+                    log::warn!("Server profile '{}' skipped: {}", name, err);
+                }
+            } else if self.servers.get(&name).is_some_and(|profile| profile.url == url) {
+                log::info!("Server {} does not serve waist, '{}' is removed", url, name);
+                self.servers.remove(&name);
+            } else {
+                continue;
+            }
+
+            self.set_servers(self.servers.clone(), Some(self.server.clone()));
+            self.apply_tokens();
+        }
+    }
+
+    fn server_url(&self) -> String {
+        self.servers
+            .get(&self.server)
+            .map_or(DEFAULT_SERVER_URL.to_string(), |profile| profile.url.clone())
+    }
+
+    fn server_token(&self, url: &str) -> Option<String> {
+        self.servers.token_of(url).map(|token| token.to_string())
+    }
+
+    /// Add the entry with the token of its server
+    fn add_entry(&self, mut entry: Entry) {
+        entry.token = self.server_token(&entry.server);
+        self.entries.write().unwrap().push(entry);
+    }
+
+    /// Use changed tokens of server profiles, channels of changed servers are requested again
+    fn apply_tokens(&mut self) {
+        let mut changed = Vec::new();
+
+        for entry in self.entries.write().unwrap().iter_mut() {
+            let token = self.servers.token_of(&entry.server).map(|token| token.to_string());
+
+            if entry.token != token {
+                entry.token = token;
+                if entry.file_name.is_none() && !entry.id.is_empty() {
+                    changed.push((entry.local_id, entry.server.clone(), entry.id.clone()));
+                }
+            }
+        }
+
+        for (local_id, server, id) in changed {
+            self.subscribe(local_id, &server, id);
+            self.reload(local_id);
+        }
+    }
+
+    pub fn download(&mut self, id: String) {
+        let local_id = self.next_id();
+        let server = self.server_url();
+
+        self.add_entry(Entry::new_with_id(local_id, id.clone(), server.clone()));
+        self.start_download(local_id, id.clone());
+        self.subscribe(local_id, &server, id);
+    }
+
+    /// Show the channel by tiles requested when they become visible
+    pub fn download_tiles(&mut self, id: String) {
+        let local_id = self.next_id();
+        let server = self.server_url();
+
+        self.add_entry(Entry::new_with_tiles(local_id, id.clone(), server.clone()));
+        self.subscribe(local_id, &server, id);
+    }
+
+    pub fn channels(&self) -> &RecentChannels {
+        &self.channels
+    }
+
+    pub fn set_channels(&mut self, channels: RecentChannels) {
+        self.channels = channels;
+    }
+
+    /// Download and subscribe to the channel of the selected server unless it is opened already
+    pub fn open_channel(&mut self, id: &str) {
+        let id = id.trim();
+
+        if id.is_empty() || id.contains(|x: char| x.is_whitespace() || x == '/') {
+            self.channel_error = Some(format!("'{}' is not a valid channel id", id));
+            return;
+        }
+
+        self.channel_error = None;
+        self.channels.push(id);
+
+        let server = self.server_url();
+        let opened = self
+            .entries
+            .read()
+            .unwrap()
+            .iter()
+            .any(|entry| entry.file_name.is_none() && entry.id == id && entry.server == server);
+
+        if !opened && self.channel_tiled {
+            self.download_tiles(id.to_string());
+        } else if !opened {
+            self.download(id.to_string());
+        }
+    }
+
+    /// Request a fresh channel id from the selected server, the channel is opened when it arrives
+    fn new_channel(&mut self) {
+        self.channel_error = None;
+        /* short request, it is not tracked */
+        Task::new_channel(
+            self.network.spawner(),
+            self.client.clone(),
+            self.server_url(),
+            self.server_token(&self.server_url()),
+            &self.minted_channels,
+        );
+    }
+
+    fn open_minted_channels(&mut self) {
+        let minted = std::mem::take(&mut *self.minted_channels.lock().unwrap());
+
+        for result in minted {
+            match result {
+                Ok(id) => {
+                    log::info!("Channel {} created", id);
+                    self.open_channel(&id);
+                }
+                Err(err) => {
+                    log::error!("Channel not created: {}", err);
+                    self.channel_error = Some(format!("Channel not created: {}", err));
+                }
+            }
+        }
+    }
+
+    fn start_download(&self, local_id: u32, id: String) {
+        Task::download(self.network.spawner(), self.client.clone(), local_id, &self.entries, id)
+            .track(&self.tasks, local_id);
+    }
+
+    fn start_upload(&self, local_id: u32) {
+        Task::upload(self.network.spawner(), self.client.clone(), local_id, &self.entries).track(&self.tasks, local_id);
+    }
+
+    /// Merge features added to the channel `id` into the entry until it is deleted
+    fn subscribe(&mut self, local_id: u32, server: &str, id: String) {
+        let client = self.client.clone();
+        let entries = Arc::clone(&self.entries);
+        let tasks = Arc::clone(&self.tasks);
+        let spawner = self.network.spawner().clone();
+        let subscription = Subscription::new(
+            self.network.spawner(),
+            self.client.clone(),
+            format!("{}/subscribe/{}", server, id),
+            self.server_token(server),
+            move |event| Task::apply_subscription_event(&spawner, &client, &entries, &tasks, local_id, &id, event),
+        );
+
+        self.subscriptions.insert(local_id, subscription);
+    }
+
+    /// Upload to the most recently opened channel
+    pub fn upload_json_array(&mut self, jsons: &mut Vec<geojson::GeoJson>) {
+        let channel = self.channels.iter().next().cloned().unwrap_or_else(default_channel);
+
+        while let Some(json) = jsons.pop() {
+            let local_id = self.next_id();
+            let server = self.server_url();
+
+            self.add_entry(Entry::new_with_json(local_id, json, server, channel.clone()));
+            self.start_upload(local_id);
+        }
+    }
+
+    /// Serialized uploads not accepted by the server yet, `None` when there are none
+    pub fn pending_uploads(&self) -> Option<String> {
+        let entries = self.entries.read().unwrap();
+        let uploads: Vec<PendingUpload> = entries
+            .iter()
+            .filter(|entry| entry.is_pending_upload())
+            .map(|entry| PendingUpload {
+                server: entry.server.clone(),
+                channel: entry.upload_channel.clone(),
+                json: entry.json.clone().unwrap(),
+            })
+            .collect();
+
+        if uploads.is_empty() {
+            return None;
+        }
+
+        match serde_json::to_string(&uploads) {
+            Ok(json_string) => Some(json_string),
+            Err(err) => {
+                log::error!("Pending uploads serialization problem: {:?}", err);
+                None
+            }
+        }
+    }
+
+    /// Queue uploads saved by `pending_uploads`
+    pub fn restore_uploads(&mut self, uploads: &str) {
+        let uploads: Vec<PendingUpload> = match serde_json::from_str(uploads) {
+            Ok(uploads) => uploads,
+            Err(err) => {
+                log::error!("Pending uploads not restored: {:?}", err);
+                return;
+            }
+        };
+
+        for upload in uploads {
+            let local_id = self.next_id();
+
+            self.add_entry(Entry::new_with_json(
+                local_id,
+                upload.json,
+                upload.server,
+                upload.channel,
+            ));
+            self.start_upload(local_id);
+        }
+    }
+
+    /// Serialized layer list with layers' settings, pending uploads are not included
+    pub fn layers(&self) -> Option<String> {
+        let entries = self.entries.read().unwrap();
+        let layers: Vec<LayerSettings> = entries
+            .iter()
+            .filter(|entry| !entry.is_pending_upload())
+            .map(|entry| LayerSettings {
+                id: entry.id.clone(),
+                server: entry.server.clone(),
+                file_name: entry.file_name.clone(),
+                path: entry.file_path.clone(),
+                json: None,
+                name: entry.name.clone(),
+                visible: entry.visible,
+                opacity: entry.opacity,
+                color: entry.color.map(Color::from_color32),
+                axis_order: entry.axis_order,
+                show_bbox: entry.show_bbox,
+                tiled: entry.tiles.is_some(),
+            })
+            .collect();
+
+        match serde_json::to_string(&layers) {
+            Ok(json_string) => Some(json_string),
+            Err(err) => {
+                log::error!("Layers serialization problem: {:?}", err);
+                None
+            }
+        }
+    }
+
+    /// Restore layers saved by `layers`, downloaded layers are requested again
+    pub fn restore_layers(&mut self, layers: &str) {
+        let layers: Vec<LayerSettings> = match serde_json::from_str(layers) {
+            Ok(layers) => layers,
+            Err(err) => {
+                log::error!("Layers not restored: {:?}", err);
+                return;
+            }
+        };
+
+        for layer in layers {
+            let local_id = self.next_id();
+            let mut entry = match (layer.file_name, layer.path, layer.json) {
+                (Some(file_name), Some(path), _) => {
+                    let imported = std::fs::read(&path)
+                        .map_err(|err| err.to_string())
+                        .and_then(|bytes| geojson_file::import(&file_name, &bytes).map_err(|err| err.to_string()));
+
+                    match imported {
+                        Ok(json) => {
+                            let mut entry = Entry::new_with_file(local_id, file_name, json);
+
+                            entry.file_path = Some(path);
+                            entry
+                        }
+                        Err(err) => {
+                            log::error!("Layer {} not restored from {}: {}", file_name, path, err);
+                            continue;
+                        }
+                    }
+                }
+                (Some(file_name), None, Some(json)) => Entry::new_with_file(local_id, file_name, json),
+                (None, _, _) if !layer.id.is_empty() && layer.tiled => {
+                    Entry::new_with_tiles(local_id, layer.id.clone(), layer.server.clone())
+                }
+                (None, _, _) if !layer.id.is_empty() => {
+                    Entry::new_with_id(local_id, layer.id.clone(), layer.server.clone())
+                }
+                _ => continue,
+            };
+
+            entry.name = layer.name;
+            entry.visible = layer.visible;
+            entry.opacity = layer.opacity.clamp(0.0, 1.0);
+            entry.color = layer.color.map(|x| x.to_color32());
+            entry.show_bbox = layer.show_bbox;
+            entry.set_axis_order(layer.axis_order);
+
+            let subscribe = entry.file_name.is_none();
+            let download = subscribe && entry.tiles.is_none();
+
+            self.add_entry(entry);
+            if download {
+                self.start_download(local_id, layer.id.clone());
+            }
+            if subscribe {
+                self.subscribe(local_id, &layer.server, layer.id);
+            }
+        }
+    }
+
+    /// Move the layer to `slot` of the list shown from the top layer, which is drawn last
+    fn move_layer(&mut self, local_id: u32, slot: usize) {
+        let mut entries = self.entries.write().unwrap();
+        let Some(from) = entries.iter().position(|entry| entry.local_id == local_id) else {
+            return;
+        };
+        let mut to = entries.len() - slot.min(entries.len());
+        let entry = entries.remove(from);
+
+        if from < to {
+            to -= 1;
+        }
+        entries.insert(to, entry);
+    }
+
+    /// Add content of the local file as a new entry, the layer is restored at start from `path` when it is known
+    pub fn import_file(&mut self, name: &str, bytes: &[u8], path: Option<String>) {
+        match geojson_file::import(name, bytes) {
+            Ok(json) => {
+                let local_id = self.next_id();
+                let mut entry = Entry::new_with_file(local_id, name.to_string(), json);
+
+                log::info!("File {} imported", name);
+                entry.file_path = path;
+                self.entries.write().unwrap().push(entry);
+                self.import_error = None;
+            }
+            Err(err) => {
+                log::error!("File {} not imported: {}", name, err);
+                self.import_error = Some(format!("{}: {}", name, err));
+            }
+        }
+    }
+
+    fn import_dropped_files(&mut self, ctx: &egui::Context) {
+        let dropped_files = ctx.input(|i| i.raw.dropped_files.clone());
+
+        for file in dropped_files {
+            let file_path = file.path.as_ref().map(|path| path.to_string_lossy().to_string());
+
+            if let Some(bytes) = file.bytes {
+                self.import_file(&file.name, &bytes, file_path);
+            } else if let Some(path) = file.path {
+                let name = path
+                    .file_name()
+                    .map_or(file.name.clone(), |x| x.to_string_lossy().to_string());
+
+                match std::fs::read(&path) {
+                    Ok(bytes) => self.import_file(&name, &bytes, file_path),
+                    Err(err) => {
+                        log::error!("File {} not read: {}", path.display(), err);
+                        self.import_error = Some(format!("{}: {}", name, err));
+                    }
+                }
+            }
+        }
+
+        let opened_files = std::mem::take(&mut *self.opened_files.lock().unwrap());
+
+        for file in opened_files {
+            match file.bytes {
+                Ok(bytes) => self.import_file(&file.name, &bytes, file.path),
+                Err(err) => {
+                    log::error!("File {} not read: {}", file.name, err);
+                    self.import_error = Some(format!("{}: {}", file.name, err));
+                }
+            }
+        }
+    }
+
+    #[cfg(all(not(target_arch = "wasm32"), not(target_os = "android")))]
+    fn open_file_dialog(&mut self) {
+        let mut dialog = rfd::AsyncFileDialog::new();
+        let opened_files = Arc::clone(&self.opened_files);
+
+        for format in geojson_file::FileFormat::ALL {
+            dialog = dialog.add_filter(format.name(), format.extensions());
+        }
+
+        let picked = dialog.pick_file();
+
+        /* picked file is read off the UI thread and imported on the next frame */
+        self.network.spawner().spawn(async move {
+            let Some(file) = picked.await else {
+                return;
+            };
+            let path = file.path().to_path_buf();
+            let bytes = {
+                let path = path.clone();
+
+                match tokio::task::spawn_blocking(move || std::fs::read(path)).await {
+                    Ok(read) => read.map_err(|err| err.to_string()),
+                    Err(err) => Err(err.to_string()),
+                }
+            };
+
+            opened_files.lock().unwrap().push(OpenedFile {
+                name: file.file_name(),
+                bytes,
+                path: Some(path.to_string_lossy().to_string()),
+            });
+        });
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn open_file_dialog(&mut self) {
+        let mut dialog = rfd::AsyncFileDialog::new();
+        let opened_files = Arc::clone(&self.opened_files);
+
+        for format in geojson_file::FileFormat::ALL {
+            dialog = dialog.add_filter(format.name(), format.extensions());
+        }
+
+        /* picked file is imported on the next frame */
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Some(file) = dialog.pick_file().await {
+                let bytes = file.read().await;
+
+                opened_files.lock().unwrap().push(OpenedFile {
+                    name: file.file_name(),
+                    bytes: Ok(bytes),
+                    path: None,
+                });
+            }
+        });
+    }
+
+    /// Runtime of the network requests, also runs the file dialogs
+    pub fn spawner(&self) -> &Spawner {
+        self.network.spawner()
+    }
+
+    /// Import dropped files, open created channels and retry failed uploads with exponential backoff,
+    /// must be called every frame
+    pub fn poll(&mut self, ctx: &egui::Context) {
+        self.import_dropped_files(ctx);
+        self.open_minted_channels();
+        self.add_probed_servers();
+
+        let now = ctx.input(|i| i.time);
+        let mut retry = Vec::new();
+        let mut next_retry: Option<f64> = None;
+        let mut in_progress = false;
+
+        for entry in self.entries.write().unwrap().iter_mut() {
+            entry.update_tiles_status();
+            in_progress |= entry.status.is_in_progress();
+            /* entries without data have nothing to retry */
+            if matches!(entry.status, EntryStatus::UploadError(_)) && entry.json.is_some() {
+                let retry_at = match entry.retry_at {
+                    Some(retry_at) => retry_at,
+                    None => {
+                        let retry_at = now + upload_retry_delay(entry.upload_attempts);
+
+                        entry.upload_attempts += 1;
+                        entry.retry_at = Some(retry_at);
+                        retry_at
+                    }
+                };
+
+                if retry_at <= now {
+                    /* status is changed right away to not schedule another retry before the task starts */
+                    entry.status = EntryStatus::Uploading;
+                    entry.retry_at = None;
+                    retry.push(entry.local_id);
+                } else {
+                    next_retry = Some(next_retry.map_or(retry_at, |x: f64| x.min(retry_at)));
+                }
+            }
+        }
+
+        for local_id in retry {
+            log::info!("Retrying upload of entry {}", local_id);
+            self.start_upload(local_id);
+        }
+
+        if let Some(retry_at) = next_retry {
+            ctx.request_repaint_after(Duration::from_secs_f64((retry_at - now).max(0.0)));
+        }
+
+        if in_progress {
+            ctx.request_repaint_after(PROGRESS_REPAINT_INTERVAL);
+        }
+    }
+
+    fn retry_upload(&mut self, local_id: u32) {
+        if let Some(entry) = self
+            .entries
+            .write()
+            .unwrap()
+            .iter_mut()
+            .find(|entry| entry.local_id == local_id)
+        {
+            entry.status = EntryStatus::Uploading;
+            entry.upload_attempts = 0;
+            entry.retry_at = None;
+        }
+        self.start_upload(local_id);
+    }
+
+    fn reload(&mut self, local_id: u32) {
+        let id = self
+            .entries
+            .write()
+            .unwrap()
+            .iter_mut()
+            .find(|entry| entry.local_id == local_id && entry.file_name.is_none())
+            .and_then(|entry| match &mut entry.tiles {
+                /* shown tiles are requested on the next frame */
+                Some(tiles) => {
+                    tiles.invalidate();
+                    None
+                }
+                None => Some(entry.id.clone()),
+            });
+
+        if let Some(id) = id {
+            self.start_download(local_id, id);
+        }
+    }
+
+    /// Stop the entry's request, the entry keeps the data it has
+    fn cancel(&mut self, local_id: u32) {
+        if let Some(task) = self.tasks.lock().unwrap().remove(&local_id) {
+            task.cancel();
+        }
+
+        if let Some(entry) = self
+            .entries
+            .write()
+            .unwrap()
+            .iter_mut()
+            .find(|entry| entry.local_id == local_id)
+        {
+            if entry.status.is_in_progress() {
+                entry.status = EntryStatus::Cancelled;
+            }
+        }
+    }
+
+    fn save_entry(&self, local_id: u32, format: geojson_file::FileFormat) {
+        let data = self
+            .entries
+            .read()
+            .unwrap()
+            .iter()
+            .find(|entry| entry.local_id == local_id)
+            .and_then(|entry| Some((entry.json.clone()?, entry.title().to_string())));
+
+        if let Some((json, title)) = data {
+            geojson_file::save(self.network.spawner(), &json, format, &title);
+        }
+    }
+
+    fn delete(&mut self, local_id: u32) {
+        let mut entries = self.entries.write().unwrap();
+        let channel = entries
+            .iter()
+            .find(|entry| entry.local_id == local_id && entry.etag.is_some())
+            .map(|entry| (entry.server.clone(), entry.id.clone()));
+
+        entries.retain(|entry| entry.local_id != local_id);
+
+        /* cached copy of the closed channel is not needed unless it is opened twice */
+        if let Some((server, id)) = channel {
+            if !entries.iter().any(|entry| entry.server == server && entry.id == id) {
+                channel_cache::remove(&server, &id);
+            }
+        }
+        drop(entries);
+        self.subscriptions.remove(&local_id);
+
+        /* the request would update the removed entry */
+        if let Some(task) = self.tasks.lock().unwrap().remove(&local_id) {
+            task.cancel();
+        }
+
+        if self
+            .selected
+            .as_ref()
+            .is_some_and(|selected| selected.local_id == local_id)
+        {
+            self.selected = None;
+        }
+    }
+
+    /// Stop subscriptions and requests, pending uploads stay in the entries to be saved
+    pub fn shutdown(&mut self) {
+        for (_, task) in self.tasks.lock().unwrap().drain() {
+            task.cancel();
+        }
+        self.subscriptions.clear();
+        self.network.shutdown();
+    }
+}
+
+impl Default for GeoJsonDispatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Extra distance in pixels around the marker to treat pointer as hovering
+const MARKER_HOVER_TOLERANCE: f32 = 3.0;
+
+/// Black or white, whichever is more readable over the `background`
+fn contrast_color(background: Color32) -> Color32 {
+    let luminance = 0.299 * background.r() as f32 + 0.587 * background.g() as f32 + 0.114 * background.b() as f32;
+
+    if luminance > 128.0 {
+        Color32::BLACK
+    } else {
+        Color32::WHITE
+    }
+}
+
+/// Distance in pixels from the pointer to a feature to treat it as clicked
+const HIT_TOLERANCE: f32 = 6.0;
+
+fn distance_to_segment(pos: Pos2, a: Pos2, b: Pos2) -> f32 {
+    let segment = b - a;
+    let length_sq = segment.length_sq();
+
+    if length_sq == 0.0 {
+        return pos.distance(a);
+    }
+
+    let t = ((pos - a).dot(segment) / length_sq).clamp(0.0, 1.0);
+    pos.distance(a + segment * t)
+}
+
+/// Even-odd rule test for the closed ring
+fn is_in_ring(pos: Pos2, ring: &[Pos2]) -> bool {
+    let mut inside = false;
+
+    for (a, b) in ring.iter().zip(ring.iter().cycle().skip(1)) {
+        if (a.y > pos.y) != (b.y > pos.y) && pos.x < (b.x - a.x) * (pos.y - a.y) / (b.y - a.y) + a.x {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn hit_geometry(value: &geojson::Value, style: &FeatureStyle, pos: Pos2, projector: &Projector) -> bool {
+    let to_screen =
+        |ring: &[Vec<f64>]| -> Vec<Pos2> { ring.iter().map(|x| pair_to_screen_coords(x, projector)).collect() };
+    let line_tolerance = style.stroke.width / 2.0 + HIT_TOLERANCE;
+    let hit_line = |line: &[Vec<f64>]| {
+        to_screen(line)
+            .windows(2)
+            .any(|pair| distance_to_segment(pos, pair[0], pair[1]) <= line_tolerance)
+    };
+    let hit_point =
+        |point: &[f64]| pair_to_screen_coords(point, projector).distance(pos) <= style.marker.size + HIT_TOLERANCE;
+    let hit_polygon = |rings: &[Vec<Vec<f64>>]| {
+        let inside = rings
+            .iter()
+            .enumerate()
+            .all(|(idx, ring)| is_in_ring(pos, &to_screen(ring)) == (idx == 0));
+
+        inside || rings.iter().any(|ring| hit_line(ring))
+    };
+
+    match value {
+        geojson::Value::Point(point) => hit_point(point),
+        geojson::Value::MultiPoint(points) => points.iter().any(|point| hit_point(point)),
+        geojson::Value::LineString(line) => hit_line(line),
+        geojson::Value::MultiLineString(lines) => lines.iter().any(|line| hit_line(line)),
+        geojson::Value::Polygon(rings) => hit_polygon(rings),
+        geojson::Value::MultiPolygon(polygons) => polygons.iter().any(|rings| hit_polygon(rings)),
+        geojson::Value::GeometryCollection(geometries) => geometries
+            .iter()
+            .any(|geometry| hit_geometry(&geometry.value, style, pos, projector)),
+    }
+}
+
+fn format_length(meters: f64) -> String {
+    if meters < 1000.0 {
+        format!("{:.0} m", meters)
+    } else {
+        format!("{:.2} km", meters / 1000.0)
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+fn format_area(square_meters: f64) -> String {
+    if square_meters < 1_000_000.0 {
+        format!("{:.0} m²", square_meters)
+    } else {
+        format!("{:.2} km²", square_meters / 1_000_000.0)
+    }
+}
+
+/// GeoJSON position is longitude first, then latitude (RFC 7946)
+fn pair_to_screen_coords(point_pair: &[f64], projector: &Projector) -> egui::Pos2 {
+    let lon = point_pair[0];
+    let lat = point_pair[1];
+
+    projector.project(walkers::Position::from_lat_lon(lat, lon)).to_pos2()
+}
 
 impl GeoJsonDispatcher {
-    fn draw_linestring(
-        &self,
-        point_pairs: &[Vec<f64>],
-        color: egui::Color32,
-        width: f32,
-        painter: &Painter,
-        projector: &Projector,
-    ) {
+    fn draw_linestring(&self, point_pairs: &[Vec<f64>], stroke: Stroke, painter: &Painter, projector: &Projector) {
         let mut iter = point_pairs.iter();
 
         if let Some(mut previous) = iter.next().map(|x| pair_to_screen_coords(x, projector)) {
             while let Some(last) = iter.next().map(|x| pair_to_screen_coords(x, projector)) {
-                painter.line_segment([previous, last], (width, color));
+                painter.line_segment([previous, last], stroke);
                 previous = last;
             }
         }
     }
 
-    fn draw_bbox(&self, _bbox: &geojson::Bbox, _painter: &Painter, _projector: &Projector) {}
+    fn draw_marker(
+        &self,
+        point_pair: &[f64],
+        marker: &Marker,
+        painter: &Painter,
+        projector: &Projector,
+        hover_pos: Option<Pos2>,
+    ) {
+        let center = pair_to_screen_coords(point_pair, projector);
+        let hovered = hover_pos.is_some_and(|pos| pos.distance(center) <= marker.size + MARKER_HOVER_TOLERANCE);
+        let (size, stroke) = if hovered {
+            (marker.size * 1.5, Stroke::new(2.0, Color32::WHITE))
+        } else {
+            (marker.size, Stroke::new(1.0, Color32::BLACK))
+        };
+
+        match marker.shape {
+            MarkerShape::Circle => {
+                painter.circle(center, size, marker.color, stroke);
+            }
+            MarkerShape::Square => {
+                painter.rect(
+                    egui::Rect::from_center_size(center, Vec2::splat(size * 2.0)),
+                    0.0,
+                    marker.color,
+                    stroke,
+                );
+            }
+            MarkerShape::Triangle => {
+                painter.add(Shape::convex_polygon(
+                    vec![
+                        center + Vec2::new(0.0, -size),
+                        center + Vec2::new(size * 0.866, size * 0.5),
+                        center + Vec2::new(-size * 0.866, size * 0.5),
+                    ],
+                    marker.color,
+                    stroke,
+                ));
+            }
+            MarkerShape::Cross => {
+                let outline = Stroke::new(stroke.width + 2.0, stroke.color);
+
+                for (a, b) in [
+                    (Vec2::new(-size, -size), Vec2::new(size, size)),
+                    (Vec2::new(-size, size), Vec2::new(size, -size)),
+                ] {
+                    painter.line_segment([center + a, center + b], outline);
+                    painter.line_segment([center + a, center + b], (2.0, marker.color));
+                }
+            }
+        }
+
+        if let Some(label) = &marker.label {
+            painter.text(
+                center,
+                Align2::CENTER_CENTER,
+                label,
+                FontId::proportional(size * 1.2),
+                contrast_color(marker.color),
+            );
+        }
+    }
+
+    /// Draw polygon: first ring is an exterior, other rings are holes
+    fn draw_polygon(&self, rings: &[Vec<Vec<f64>>], style: &FeatureStyle, painter: &Painter, projector: &Projector) {
+        let rings: Vec<Vec<Pos2>> = rings
+            .iter()
+            .map(|ring| {
+                let mut ring: Vec<Pos2> = ring.iter().map(|x| pair_to_screen_coords(x, projector)).collect();
+
+                /* GeoJSON rings are closed, drop the duplicated last position */
+                if ring.len() > 1 && ring.first() == ring.last() {
+                    ring.pop();
+                }
+                ring
+            })
+            .filter(|ring| ring.len() >= 3)
+            .collect();
 
-    fn draw_feature(&self, feature: &geojson::Feature, painter: &Painter, projector: &Projector) {
-        if feature.geometry.is_none() {
+        if rings.is_empty() {
             return;
         }
 
-        let extract_props = || {
-            if let (Some(color), Some(width)) = (feature.property("color"), feature.property("width")) {
-                (
-                    (color
-                        .as_str()
-                        .unwrap()
-                        .parse::<Color>()
-                        .map_or(None, |x| Some(x.to_color32()))),
-                    width.as_f64().map(|x| x as f32),
-                )
-            } else {
-                (None, None)
+        if style.fill != Color32::TRANSPARENT {
+            let mut vertices = Vec::new();
+            let mut hole_indices = Vec::new();
+
+            for (idx, ring) in rings.iter().enumerate() {
+                if idx > 0 {
+                    hole_indices.push(vertices.len() / 2);
+                }
+                for pos in ring {
+                    vertices.push(pos.x);
+                    vertices.push(pos.y);
+                }
             }
-        };
 
-        if let Some(ref geometry) = feature.geometry {
-            if let (Some(color), Some(width)) = extract_props() {
-                match geometry.value {
-                    geojson::Value::Point(_) => {}
-                    geojson::Value::MultiPoint(_) => {}
-                    geojson::Value::LineString(ref linestring) => {
-                        self.draw_linestring(linestring, color, width, painter, projector)
+            match earcutr::earcut(&vertices, &hole_indices, 2) {
+                Ok(indices) => {
+                    let mut mesh = Mesh::default();
+
+                    for pos in rings.iter().flatten() {
+                        mesh.colored_vertex(*pos, style.fill);
                     }
-                    geojson::Value::MultiLineString(_) => {}
-                    geojson::Value::Polygon(_) => {}
-                    geojson::Value::MultiPolygon(_) => {}
-                    geojson::Value::GeometryCollection(_) => {}
+                    for triangle in indices.chunks_exact(3) {
+                        mesh.add_triangle(triangle[0] as u32, triangle[1] as u32, triangle[2] as u32);
+                    }
+                    painter.add(mesh);
+                }
+                Err(err) => log::warn!("Polygon triangulation failed: {:?}", err),
+            }
+        }
+
+        for ring in rings {
+            painter.add(Shape::closed_line(ring, style.stroke));
+        }
+    }
+
+    fn draw_bbox(&self, bbox: &AABB<[f64; 2]>, painter: &Painter, projector: &Projector) {
+        let lower = bbox.lower();
+        let upper = bbox.upper();
+        let a = pair_to_screen_coords(&[lower[0], upper[1]], projector);
+        let c = pair_to_screen_coords(&[upper[0], lower[1]], projector);
+        let b = Pos2::new(c.x, a.y);
+        let d = Pos2::new(a.x, c.y);
+
+        painter.extend(Shape::dashed_line(
+            &[a, b, c, d, a],
+            (1.0, Color32::BLACK.gamma_multiply(0.60)),
+            6.0,
+            4.0,
+        ));
+    }
+
+    fn draw_geometry(
+        &self,
+        value: &geojson::Value,
+        style: &FeatureStyle,
+        painter: &Painter,
+        projector: &Projector,
+        hover_pos: Option<Pos2>,
+    ) {
+        match value {
+            geojson::Value::Point(point) => self.draw_marker(point, &style.marker, painter, projector, hover_pos),
+            geojson::Value::MultiPoint(points) => {
+                for point in points {
+                    self.draw_marker(point, &style.marker, painter, projector, hover_pos);
+                }
+            }
+            geojson::Value::LineString(linestring) => {
+                self.draw_linestring(linestring, style.stroke, painter, projector)
+            }
+            geojson::Value::MultiLineString(linestrings) => {
+                for linestring in linestrings {
+                    self.draw_linestring(linestring, style.stroke, painter, projector);
+                }
+            }
+            geojson::Value::Polygon(polygon) => self.draw_polygon(polygon, style, painter, projector),
+            geojson::Value::MultiPolygon(polygons) => {
+                for polygon in polygons {
+                    self.draw_polygon(polygon, style, painter, projector);
+                }
+            }
+            geojson::Value::GeometryCollection(geometries) => {
+                for geometry in geometries {
+                    self.draw_geometry(&geometry.value, style, painter, projector, hover_pos);
+                }
+            }
+        }
+    }
+
+    /// Draw received tiles covering the visible area, a missing tile is replaced by its nearest received parent
+    fn draw_tiles(
+        &self,
+        entry: &Entry,
+        tiles: &TileCache,
+        visible: &[TileId],
+        painter: &Painter,
+        projector: &Projector,
+        hover_pos: Option<Pos2>,
+    ) {
+        for tile in visible {
+            let Some((_, cached)) = tiles.received(tile) else {
+                continue;
+            };
+
+            /* geometries are buffered around tiles, the buffer overlaps neighbouring tiles */
+            let painter = painter.with_clip_rect(tile_rect(tile, projector));
+
+            for feature in &cached.features {
+                if let Some(geometry) = &feature.geometry {
+                    let style = entry.style(feature.properties.as_ref());
+
+                    self.draw_geometry(&geometry.value, &style, &painter, projector, hover_pos);
                 }
             }
         }
     }
 
-    fn draw_feature_collection(
+    fn draw_entry(
         &self,
-        feature_collection: &geojson::FeatureCollection,
+        entry: &Entry,
+        area: &AABB<[f64; 2]>,
+        visible_tiles: &[TileId],
         painter: &Painter,
         projector: &Projector,
+        hover_pos: Option<Pos2>,
     ) {
-        if let Some(bbox) = &feature_collection.bbox {
-            self.draw_bbox(bbox, painter, projector);
+        if let Some(tiles) = &entry.tiles {
+            self.draw_tiles(entry, tiles, visible_tiles, painter, projector, hover_pos);
+            return;
+        }
+
+        if entry.show_bbox {
+            if let Some(extent) = entry.extent() {
+                self.draw_bbox(&extent, painter, projector);
+            }
         }
 
-        for feature in &feature_collection.features {
-            self.draw_feature(feature, painter, projector);
+        if let Some(json) = &entry.json {
+            for idx in entry.features_in(area) {
+                let (geometry, properties) = match json {
+                    GeoJson::Geometry(geometry) => (Some(geometry), None),
+                    GeoJson::Feature(feature) => (feature.geometry.as_ref(), feature.properties.as_ref()),
+                    GeoJson::FeatureCollection(fc) => {
+                        (fc.features[idx].geometry.as_ref(), fc.features[idx].properties.as_ref())
+                    }
+                };
+
+                if let Some(geometry) = geometry {
+                    self.draw_geometry(&geometry.value, &entry.style(properties), painter, projector, hover_pos);
+                }
+            }
         }
     }
 }
 
-impl Plugin for &GeoJsonDispatcher {
-    fn run(&mut self, _response: &Response, painter: Painter, projector: &Projector) {
-        for entry in self.entries.read().unwrap().iter() {
+/// Screen rectangle of the tile
+fn tile_rect(tile: &TileId, projector: &Projector) -> Rect {
+    let envelope = tile.envelope();
+
+    Rect::from_two_pos(
+        pair_to_screen_coords(&envelope.lower(), projector),
+        pair_to_screen_coords(&envelope.upper(), projector),
+    )
+}
+
+impl GeoJsonDispatcher {
+    /// Find the topmost feature under `pos`
+    fn hit_test(
+        &self,
+        pos: Pos2,
+        area: &AABB<[f64; 2]>,
+        visible_tiles: &[TileId],
+        projector: &Projector,
+    ) -> Option<SelectedFeature> {
+        for entry in self.entries.read().unwrap().iter().rev() {
             if !entry.visible {
                 continue;
             }
 
-            if let Some(json) = &entry.json {
-                match json {
-                    GeoJson::Geometry(_) => {}
-                    GeoJson::Feature(_) => {}
-                    GeoJson::FeatureCollection(fc) => self.draw_feature_collection(fc, &painter, projector),
+            if let Some(tiles) = &entry.tiles {
+                /* features are drawn clipped by the visible tile under `pos` */
+                let Some((tile, cached)) = visible_tiles
+                    .iter()
+                    .find(|tile| tile_rect(tile, projector).contains(pos))
+                    .and_then(|tile| tiles.received(tile))
+                else {
+                    continue;
+                };
+
+                for (idx, feature) in cached.features.iter().enumerate().rev() {
+                    if let Some(geometry) = &feature.geometry {
+                        let style = entry.style(feature.properties.as_ref());
+
+                        if hit_geometry(&geometry.value, &style, pos, projector) {
+                            return Some(SelectedFeature {
+                                local_id: entry.local_id,
+                                tile: Some(tile),
+                                idx,
+                            });
+                        }
+                    }
+                }
+                continue;
+            }
+
+            for idx in entry.features_in(area).into_iter().rev() {
+                if let Some(feature) = entry.feature(idx) {
+                    if let Some(geometry) = &feature.geometry {
+                        let style = entry.style(feature.properties.as_ref());
+
+                        if hit_geometry(&geometry.value, &style, pos, projector) {
+                            return Some(SelectedFeature {
+                                local_id: entry.local_id,
+                                tile: None,
+                                idx,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Size of map tiles in pixels to compute zoom levels
+const TILE_SIZE: f64 = 256.0;
+
+/// Extra pixels around the screen to draw features which stick out of their envelopes like markers
+const VISIBLE_AREA_MARGIN: f32 = 16.0;
+
+/// Visible part of the map in lon/lat
+fn visible_area(painter: &Painter, projector: &Projector) -> AABB<[f64; 2]> {
+    let half_size = painter.clip_rect().expand(VISIBLE_AREA_MARGIN).size() / 2.0;
+    let a = projector.unproject(-half_size);
+    let c = projector.unproject(half_size);
+
+    AABB::from_corners([a.lon(), a.lat()], [c.lon(), c.lat()])
+}
+
+impl GeoJsonDispatcher {
+    /// Request tiles of visible tiled entries which are not received yet, returns whether any is requested
+    fn request_tiles(&mut self, visible: &[TileId]) -> bool {
+        let mut requests = Vec::new();
+
+        for entry in self.entries.write().unwrap().iter_mut().filter(|entry| entry.visible) {
+            if let Some(tiles) = &mut entry.tiles {
+                tiles.next_frame();
+                for tile in tiles.show(visible) {
+                    let url = format!(
+                        "{}/tile/{}/{}/{}/{}.mvt",
+                        entry.server, entry.id, tile.z, tile.x, tile.y
+                    );
+
+                    requests.push((entry.local_id, url, entry.token.clone(), tile));
                 }
             }
         }
+
+        let requested = !requests.is_empty();
+
+        /* tiles' requests are short and many, they are not tracked */
+        for (local_id, url, token, tile) in requests {
+            Task::tile(
+                self.network.spawner(),
+                self.client.clone(),
+                local_id,
+                &self.entries,
+                url,
+                token,
+                tile,
+            );
+        }
+
+        requested
+    }
+}
+
+impl Plugin for &mut GeoJsonDispatcher {
+    fn run(&mut self, response: &Response, painter: Painter, projector: &Projector) {
+        let hover_pos = response.hover_pos();
+        let area = visible_area(&painter, projector);
+        let zoom = vector_tiles::tile_zoom(&area, painter.clip_rect().width(), TILE_SIZE);
+        let visible_tiles = vector_tiles::covering(&area, zoom);
+
+        if self.request_tiles(&visible_tiles) {
+            response.ctx.request_repaint_after(PROGRESS_REPAINT_INTERVAL);
+        }
+
+        if response.clicked_by(PointerButton::Primary) {
+            if let Some(pos) = response.interact_pointer_pos() {
+                self.selected = self.hit_test(pos, &area, &visible_tiles, projector);
+            }
+        }
+
+        for entry in self.entries.read().unwrap().iter() {
+            if entry.visible {
+                self.draw_entry(entry, &area, &visible_tiles, &painter, projector, hover_pos);
+            }
+        }
     }
 }
 
 impl GeoJsonDispatcher {
-    pub fn show_ui(&mut self, ui: &Ui) {
-        if self.entries.read().unwrap().is_empty() {
+    fn show_ui_selected(&mut self, ui: &Ui) {
+        let Some(selected) = &self.selected else {
+            return;
+        };
+
+        let feature = self
+            .entries
+            .read()
+            .unwrap()
+            .iter()
+            .find(|entry| entry.local_id == selected.local_id)
+            .and_then(|entry| entry.selected_feature(selected));
+
+        let Some(feature) = feature else {
+            self.selected = None;
+            return;
+        };
+
+        let mut open = true;
+
+        Window::new("Feature")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::RIGHT_BOTTOM, [-10., -40.])
+            .show(ui.ctx(), |ui| {
+                Grid::new("Feature properties")
+                    .num_columns(2)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        if let Some(geometry) = &feature.geometry {
+                            let length = geojson_geometry::length(&geometry.value);
+                            let area = geojson_geometry::area(&geometry.value);
+
+                            ui.label("Geometry");
+                            ui.label(geometry.value.type_name());
+                            ui.end_row();
+                            ui.label("Vertices");
+                            ui.label(geojson_geometry::vertex_count(&geometry.value).to_string());
+                            ui.end_row();
+                            if length > 0.0 {
+                                ui.label("Length");
+                                ui.label(format_length(length));
+                                ui.end_row();
+                            }
+                            if area > 0.0 {
+                                ui.label("Area");
+                                ui.label(format_area(area));
+                                ui.end_row();
+                            }
+                        }
+
+                        for (key, value) in feature.properties.iter().flatten() {
+                            ui.label(RichText::new(key).strong());
+                            match value.as_str() {
+                                Some(string) => ui.label(string),
+                                None => ui.label(value.to_string()),
+                            };
+                            ui.end_row();
+                        }
+                    });
+
+                if ui.button("Copy as GeoJSON").clicked() {
+                    ui.output_mut(|output| output.copied_text = feature.to_string());
+                }
+            });
+
+        if !open {
+            self.selected = None;
+        }
+    }
+
+    fn zoom_to_entry(&self, local_id: u32, viewport: Vec2, map_memory: &mut MapMemory) {
+        let extent = self
+            .entries
+            .read()
+            .unwrap()
+            .iter()
+            .find(|entry| entry.local_id == local_id)
+            .and_then(|entry| entry.extent());
+
+        if let Some(extent) = extent {
+            let (center, zoom) = geojson_geometry::fit_extent(&extent, viewport, TILE_SIZE);
+
+            map_memory.center_at(center);
+            super::zoom_to(map_memory, zoom);
+        }
+    }
+
+    fn show_ui_servers(&mut self, ui: &mut Ui) {
+        ui.collapsing("Server", |ui| {
+            ui.horizontal(|ui| {
+                ComboBox::from_id_source("Server profile")
+                    .selected_text(self.server.as_str())
+                    .show_ui(ui, |ui| {
+                        for profile in self.servers.iter() {
+                            ui.selectable_value(&mut self.server, profile.name.clone(), profile.name.as_str())
+                                .on_hover_text(profile.url.as_str());
+                        }
+                    });
+
+                if ui.button("🗑").on_hover_text("Remove server profile").clicked() {
+                    let name = self.server.clone();
+
+                    self.servers.remove(&name);
+                    self.set_servers(self.servers.clone(), None);
+                    self.apply_tokens();
+                }
+            });
+
+            ui.horizontal(|ui| {
+                let has_token = self
+                    .servers
+                    .get(&self.server)
+                    .is_some_and(|profile| profile.token.is_some());
+
+                ui.add(
+                    TextEdit::singleline(&mut self.token_input)
+                        .password(true)
+                        .hint_text(if has_token { "token is set" } else { "token" }),
+                );
+
+                if ui
+                    .button("🔑")
+                    .on_hover_text("Set token of the server profile, empty to use anonymous access")
+                    .clicked()
+                {
+                    let token = std::mem::take(&mut self.token_input);
+
+                    self.servers.set_token(&self.server, &token);
+                    self.apply_tokens();
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.add(
+                    TextEdit::singleline(&mut self.new_server_name)
+                        .hint_text("name")
+                        .desired_width(60.0),
+                );
+                ui.add(TextEdit::singleline(&mut self.new_server_url).hint_text("https://"));
+
+                if ui.button("➕").on_hover_text("Add server profile").clicked() {
+                    match self.servers.insert(&self.new_server_name, &self.new_server_url) {
+                        Ok(_) => {
+                            self.server_error = None;
+                            self.new_server_name.clear();
+                            self.new_server_url.clear();
+                            self.apply_tokens();
+                        }
+                        Err(err) => self.server_error = Some(err),
+                    }
+                }
+            });
+
+            if let Some(error) = &self.server_error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+        });
+    }
+
+    fn show_ui_channels(&mut self, ui: &mut Ui) {
+        ui.collapsing("Channels", |ui| {
+            ui.horizontal(|ui| {
+                let input = ui.add(
+                    TextEdit::singleline(&mut self.channel_input)
+                        .hint_text("channel id")
+                        .desired_width(120.0),
+                );
+                let submitted = input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+
+                if ui.button("Open").on_hover_text("Open channel").clicked() || submitted {
+                    let id = std::mem::take(&mut self.channel_input);
+
+                    self.open_channel(&id);
+                }
+
+                if ui.button("✨").on_hover_text("New channel").clicked() {
+                    self.new_channel();
+                }
+            });
+
+            ui.checkbox(&mut self.channel_tiled, "Tiles")
+                .on_hover_text("Download only visible tiles of large channels");
+
+            if let Some(error) = &self.channel_error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+
+            let mut open = None;
+            let mut forget = None;
+
+            for id in self.channels.iter() {
+                ui.horizontal(|ui| {
+                    if ui.link(id.as_str()).on_hover_text("Open channel").clicked() {
+                        open = Some(id.clone());
+                    }
+                    if ui.small_button("🗑").on_hover_text("Forget channel").clicked() {
+                        forget = Some(id.clone());
+                    }
+                });
+            }
+
+            if let Some(id) = open {
+                self.open_channel(&id);
+            }
+            if let Some(id) = forget {
+                self.channels.remove(&id);
+            }
+        });
+    }
+
+    /// Show where the dragged layer goes and move it when the pointer is released
+    fn show_ui_drag(&mut self, ui: &Ui, rows: &[Rect]) {
+        let Some(local_id) = self.dragged else {
             return;
+        };
+        let Some(pointer) = ui.input(|i| i.pointer.interact_pos()) else {
+            return;
+        };
+
+        let slot = rows
+            .iter()
+            .position(|rect| pointer.y < rect.center().y)
+            .unwrap_or(rows.len());
+        let y = match rows.get(slot) {
+            Some(rect) => rect.top(),
+            None => rows.last().map_or(pointer.y, |rect| rect.bottom()),
+        };
+        let stroke = ui.visuals().selection.stroke;
+
+        ui.painter()
+            .hline(ui.max_rect().x_range(), y, Stroke::new(2.0, stroke.color));
+
+        if !ui.input(|i| i.pointer.any_down()) {
+            self.dragged = None;
+            self.move_layer(local_id, slot);
         }
-        Window::new("")
+    }
+
+    pub fn show_ui(&mut self, ui: &Ui, map_memory: &mut MapMemory) {
+        self.show_ui_selected(ui);
+
+        let action = Window::new("")
             .anchor(Align2::RIGHT_TOP, [-10., 30.])
             .interactable(true)
             .show(ui.ctx(), |ui| {
-                self.entries
-                    .write()
-                    .unwrap()
-                    .iter_mut()
-                    .for_each(|entry| entry.show_ui(ui));
+                self.show_ui_servers(ui);
+                self.show_ui_channels(ui);
+
+                #[cfg(not(target_os = "android"))]
+                if ui
+                    .button("📂 Open file…")
+                    .on_hover_text("Import GeoJSON, GPX or KML file, files can be dropped on the map too")
+                    .clicked()
+                {
+                    self.open_file_dialog();
+                }
+
+                if let Some(error) = &self.import_error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+
+                let mut action = None;
+                let mut rows: Vec<Rect> = Vec::new();
+
+                /* the top layer is drawn last */
+                let mut tasks = self.tasks.lock().unwrap();
+
+                forget_finished(&mut tasks);
+
+                for entry in self.entries.write().unwrap().iter_mut().rev() {
+                    let progress = tasks.get(&entry.local_id).map(|task| task.progress());
+                    let row = ui.horizontal(|ui| entry.show_ui(ui, progress));
+                    let (handle, entry_action) = row.inner;
+
+                    if handle.drag_started() {
+                        self.dragged = Some(entry.local_id);
+                    }
+                    if let Some(entry_action) = entry_action {
+                        action = Some((entry.local_id, entry_action));
+                    }
+                    rows.push(row.response.rect);
+                }
+
+                drop(tasks);
+                self.show_ui_drag(ui, &rows);
+                action
             });
+
+        match action.and_then(|x| x.inner).flatten() {
+            Some((local_id, EntryAction::ZoomTo)) => self.zoom_to_entry(local_id, ui.max_rect().size(), map_memory),
+            Some((local_id, EntryAction::RetryUpload)) => self.retry_upload(local_id),
+            Some((local_id, EntryAction::Reload)) => self.reload(local_id),
+            Some((local_id, EntryAction::Cancel)) => self.cancel(local_id),
+            Some((local_id, EntryAction::Delete)) => self.delete(local_id),
+            Some((local_id, EntryAction::SaveAs(format))) => self.save_entry(local_id, format),
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_axes_are_swapped() {
+        let mut json: GeoJson = r#"{
+            "type": "FeatureCollection",
+            "bbox": [59.0, 30.0, 61.0, 31.0],
+            "features": [{
+                "type": "Feature",
+                "properties": null,
+                "geometry": {"type": "Polygon", "coordinates": [[[59.0, 30.0], [61.0, 30.0], [61.0, 31.0], [59.0, 30.0]]]}
+            }, {
+                "type": "Feature",
+                "properties": null,
+                "geometry": {"type": "LineString", "bbox": [59.0, 30.0, 0.0, 60.0, 31.0, 0.0],
+                             "coordinates": [[59.0, 30.0, 0.0], [60.0, 31.0, 0.0]]}
+            }]
+        }"#
+        .parse()
+        .unwrap();
+        let original = json.clone();
+
+        swap_geojson_axes(&mut json);
+
+        let GeoJson::FeatureCollection(fc) = &json else {
+            panic!("feature collection is lost");
+        };
+        assert_eq!(fc.bbox, Some(vec![30.0, 59.0, 31.0, 61.0]));
+        assert_eq!(
+            fc.features[0].geometry.as_ref().unwrap().value,
+            geojson::Value::Polygon(vec![vec![
+                vec![30.0, 59.0],
+                vec![30.0, 61.0],
+                vec![31.0, 61.0],
+                vec![30.0, 59.0]
+            ]])
+        );
+        let line = fc.features[1].geometry.as_ref().unwrap();
+        assert_eq!(line.bbox, Some(vec![30.0, 59.0, 0.0, 31.0, 60.0, 0.0]));
+        assert_eq!(
+            line.value,
+            geojson::Value::LineString(vec![vec![30.0, 59.0, 0.0], vec![31.0, 60.0, 0.0]])
+        );
+
+        swap_geojson_axes(&mut json);
+
+        assert_eq!(json, original);
+    }
+
+    #[test]
+    fn entries_without_data_are_not_uploaded() {
+        let entries = Arc::new(RwLock::new(vec![Entry::new_with_id(
+            1,
+            String::new(),
+            DEFAULT_SERVER_URL.to_string(),
+        )]));
+
+        futures::executor::block_on(Task::run_upload(
+            Client::new(),
+            1,
+            Arc::clone(&entries),
+            Default::default(),
+        ));
+
+        assert!(matches!(entries.read().unwrap()[0].status, EntryStatus::UploadError(_)));
     }
 }
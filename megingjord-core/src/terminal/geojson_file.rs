@@ -0,0 +1,850 @@
+//! Conversion between GeoJSON overlays and local GeoJSON, GPX and KML files
+use super::geojson_style::FeatureStyle;
+use super::network::Spawner;
+use egui::Color32;
+use geojson::{Feature, FeatureCollection, GeoJson, Geometry, JsonObject, JsonValue, Position, Value};
+use roxmltree::Node;
+use std::fmt::{Display, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    GeoJson,
+    Gpx,
+    Kml,
+}
+
+impl FileFormat {
+    pub const ALL: [FileFormat; 3] = [FileFormat::GeoJson, FileFormat::Gpx, FileFormat::Kml];
+
+    /// Guess format by the file's extension
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        let (_, extension) = name.rsplit_once('.')?;
+
+        match extension.to_ascii_lowercase().as_str() {
+            "geojson" | "json" => Some(Self::GeoJson),
+            "gpx" => Some(Self::Gpx),
+            "kml" => Some(Self::Kml),
+            _ => None,
+        }
+    }
+
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            Self::GeoJson => &["geojson", "json"],
+            Self::Gpx => &["gpx"],
+            Self::Kml => &["kml"],
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Self::GeoJson => "application/geo+json",
+            Self::Gpx => "application/gpx+xml",
+            Self::Kml => "application/vnd.google-earth.kml+xml",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::GeoJson => "GeoJSON",
+            Self::Gpx => "GPX",
+            Self::Kml => "KML",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    UnknownFormat,
+    NotUtf8,
+    GeoJson(String),
+    Xml(String),
+    NoFeatures,
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Read file `name` with `bytes` content, format is detected by the file's extension
+pub fn import(name: &str, bytes: &[u8]) -> Result<GeoJson, ImportError> {
+    let format = FileFormat::from_file_name(name).ok_or(ImportError::UnknownFormat)?;
+    let text = std::str::from_utf8(bytes).map_err(|_| ImportError::NotUtf8)?;
+    let text = text.trim_start_matches('\u{feff}');
+
+    let features = match format {
+        FileFormat::GeoJson => return text.parse::<GeoJson>().map_err(|e| ImportError::GeoJson(e.to_string())),
+        FileFormat::Gpx => gpx_features(&parse_xml(text)?),
+        FileFormat::Kml => kml_features(&parse_xml(text)?),
+    };
+
+    if features.is_empty() {
+        return Err(ImportError::NoFeatures);
+    }
+
+    Ok(GeoJson::FeatureCollection(FeatureCollection {
+        bbox: None,
+        features,
+        foreign_members: None,
+    }))
+}
+
+fn parse_xml(text: &str) -> Result<roxmltree::Document<'_>, ImportError> {
+    roxmltree::Document::parse(text).map_err(|e| ImportError::Xml(e.to_string()))
+}
+
+fn feature(value: Value, properties: JsonObject) -> Feature {
+    Feature {
+        bbox: None,
+        geometry: Some(Geometry::new(value)),
+        id: None,
+        properties: if properties.is_empty() { None } else { Some(properties) },
+        foreign_members: None,
+    }
+}
+
+/// Elements are matched by the local name, namespaces are ignored
+fn children<'a, 'input>(node: Node<'a, 'input>, name: &'a str) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == name)
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &'a str) -> Option<Node<'a, 'input>> {
+    children(node, name).next()
+}
+
+fn child_text(node: Node, name: &str) -> Option<String> {
+    child(node, name)
+        .and_then(|child| child.text())
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty())
+}
+
+/// Copy text of `names` children into properties with the same names
+fn text_properties(node: Node, names: &[&str]) -> JsonObject {
+    let mut properties = JsonObject::new();
+
+    for name in names {
+        if let Some(text) = child_text(node, name) {
+            properties.insert(name.to_string(), JsonValue::from(text));
+        }
+    }
+
+    properties
+}
+
+fn gpx_position(node: Node) -> Option<Position> {
+    let lat = node.attribute("lat")?.trim().parse::<f64>().ok()?;
+    let lon = node.attribute("lon")?.trim().parse::<f64>().ok()?;
+
+    match child_text(node, "ele").and_then(|ele| ele.parse::<f64>().ok()) {
+        Some(ele) => Some(vec![lon, lat, ele]),
+        None => Some(vec![lon, lat]),
+    }
+}
+
+fn gpx_points(node: Node, name: &str) -> Vec<Position> {
+    children(node, name).filter_map(gpx_position).collect()
+}
+
+/// Waypoints, routes and tracks of GPX 1.0 and 1.1
+fn gpx_features(document: &roxmltree::Document) -> Vec<Feature> {
+    let root = document.root_element();
+    let names = ["name", "desc", "cmt", "type"];
+    let mut features = Vec::new();
+
+    for wpt in children(root, "wpt") {
+        if let Some(position) = gpx_position(wpt) {
+            features.push(feature(Value::Point(position), text_properties(wpt, &names)));
+        }
+    }
+
+    for rte in children(root, "rte") {
+        let points = gpx_points(rte, "rtept");
+
+        if points.len() >= 2 {
+            features.push(feature(Value::LineString(points), text_properties(rte, &names)));
+        }
+    }
+
+    for trk in children(root, "trk") {
+        let segments: Vec<Vec<Position>> = children(trk, "trkseg")
+            .map(|trkseg| gpx_points(trkseg, "trkpt"))
+            .filter(|points| points.len() >= 2)
+            .collect();
+
+        let value = match segments.len() {
+            0 => continue,
+            1 => Value::LineString(segments.into_iter().next().unwrap()),
+            _ => Value::MultiLineString(segments),
+        };
+
+        features.push(feature(value, text_properties(trk, &names)));
+    }
+
+    features
+}
+
+/// KML coordinates: "lon,lat[,alt]" tuples separated by whitespace
+fn kml_coordinates(node: Node) -> Vec<Position> {
+    let Some(text) = child(node, "coordinates").and_then(|x| x.text()) else {
+        return Vec::new();
+    };
+
+    text.split_whitespace()
+        .filter_map(|tuple| {
+            tuple
+                .split(',')
+                .map(|x| x.trim().parse::<f64>().ok())
+                .collect::<Option<Position>>()
+        })
+        .filter(|position| position.len() >= 2)
+        .collect()
+}
+
+fn kml_ring(boundary: Node) -> Option<Vec<Position>> {
+    let ring = kml_coordinates(child(boundary, "LinearRing")?);
+
+    if ring.len() >= 4 {
+        Some(ring)
+    } else {
+        None
+    }
+}
+
+fn kml_geometry(node: Node) -> Option<Value> {
+    match node.tag_name().name() {
+        "Point" => kml_coordinates(node).into_iter().next().map(Value::Point),
+        "LineString" | "LinearRing" => {
+            let line = kml_coordinates(node);
+
+            if line.len() >= 2 {
+                Some(Value::LineString(line))
+            } else {
+                None
+            }
+        }
+        "Polygon" => {
+            let exterior = kml_ring(child(node, "outerBoundaryIs")?)?;
+            let holes = children(node, "innerBoundaryIs").filter_map(kml_ring);
+
+            Some(Value::Polygon(std::iter::once(exterior).chain(holes).collect()))
+        }
+        "MultiGeometry" => {
+            let geometries: Vec<Geometry> = node
+                .children()
+                .filter(|child| child.is_element())
+                .filter_map(kml_geometry)
+                .map(Geometry::new)
+                .collect();
+
+            if geometries.is_empty() {
+                None
+            } else {
+                Some(Value::GeometryCollection(geometries))
+            }
+        }
+        _ => None,
+    }
+}
+
+/// KML color "aabbggrr" to simplestyle color and opacity
+fn kml_color(node: Node) -> Option<(String, f64)> {
+    let color = child_text(node, "color")?;
+
+    if color.len() != 8 || !color.chars().all(|x| x.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let alpha = u8::from_str_radix(&color[0..2], 16).ok()? as f64 / 255.0;
+
+    Some((format!("#{}{}{}", &color[6..8], &color[4..6], &color[2..4]), alpha))
+}
+
+/// Inline `Style` of the placemark as simplestyle-spec properties, shared styles are not resolved
+fn kml_style(placemark: Node, properties: &mut JsonObject) {
+    let Some(style) = child(placemark, "Style") else {
+        return;
+    };
+
+    if let Some(line_style) = child(style, "LineStyle") {
+        if let Some((color, opacity)) = kml_color(line_style) {
+            properties.insert("stroke".to_string(), JsonValue::from(color));
+            properties.insert("stroke-opacity".to_string(), JsonValue::from(opacity));
+        }
+        if let Some(width) = child_text(line_style, "width").and_then(|x| x.parse::<f64>().ok()) {
+            properties.insert("stroke-width".to_string(), JsonValue::from(width));
+        }
+    }
+
+    if let Some((color, opacity)) = child(style, "PolyStyle").and_then(kml_color) {
+        properties.insert("fill".to_string(), JsonValue::from(color));
+        properties.insert("fill-opacity".to_string(), JsonValue::from(opacity));
+    }
+
+    if let Some((color, _)) = child(style, "IconStyle").and_then(kml_color) {
+        properties.insert("marker-color".to_string(), JsonValue::from(color));
+    }
+}
+
+/// Placemarks from any level of folders and documents
+fn kml_features(document: &roxmltree::Document) -> Vec<Feature> {
+    document
+        .descendants()
+        .filter(|node| node.is_element() && node.tag_name().name() == "Placemark")
+        .filter_map(|placemark| {
+            let value = placemark
+                .children()
+                .filter(|child| child.is_element())
+                .find_map(kml_geometry)?;
+            let mut properties = text_properties(placemark, &["name", "description"]);
+
+            kml_style(placemark, &mut properties);
+            Some(feature(value, properties))
+        })
+        .collect()
+}
+
+/// Features of the data, bare geometry is wrapped into a feature
+fn features_of(json: &GeoJson) -> Vec<Feature> {
+    match json {
+        GeoJson::Geometry(geometry) => vec![Feature::from(geometry.clone())],
+        GeoJson::Feature(feature) => vec![feature.clone()],
+        GeoJson::FeatureCollection(fc) => fc.features.clone(),
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn property_text(feature: &Feature, keys: &[&str]) -> Option<String> {
+    keys.iter().find_map(|key| match feature.property(key)? {
+        JsonValue::String(text) => Some(text.clone()),
+        JsonValue::Number(number) => Some(number.to_string()),
+        _ => None,
+    })
+}
+
+/// Write `<tag>text</tag>` when the feature has one of `keys` properties
+fn write_property(out: &mut String, feature: &Feature, keys: &[&str], tag: &str) {
+    if let Some(text) = property_text(feature, keys) {
+        let _ = write!(out, "<{tag}>{}</{tag}>", xml_escape(&text));
+    }
+}
+
+/// Points and lines of the geometry, GPX has no polygons so rings are written as lines
+fn gpx_parts<'a>(value: &'a Value, points: &mut Vec<&'a Position>, lines: &mut Vec<&'a [Position]>) {
+    match value {
+        Value::Point(point) => points.push(point),
+        Value::MultiPoint(multi_points) => points.extend(multi_points.iter()),
+        Value::LineString(line) => lines.push(line),
+        Value::MultiLineString(multi_lines) | Value::Polygon(multi_lines) => {
+            lines.extend(multi_lines.iter().map(|line| line.as_slice()))
+        }
+        Value::MultiPolygon(polygons) => lines.extend(polygons.iter().flatten().map(|line| line.as_slice())),
+        Value::GeometryCollection(geometries) => geometries
+            .iter()
+            .for_each(|geometry| gpx_parts(&geometry.value, points, lines)),
+    }
+}
+
+fn write_gpx_point(out: &mut String, tag: &str, position: &Position) {
+    if position.len() < 2 {
+        return;
+    }
+
+    let _ = write!(out, "<{tag} lat=\"{}\" lon=\"{}\">", position[1], position[0]);
+    if let Some(ele) = position.get(2) {
+        let _ = write!(out, "<ele>{}</ele>", ele);
+    }
+    let _ = write!(out, "</{tag}>");
+}
+
+fn export_gpx(json: &GeoJson) -> String {
+    let features = features_of(json);
+    let mut waypoints = String::new();
+    let mut tracks = String::new();
+
+    for feature in features.iter() {
+        let Some(geometry) = &feature.geometry else {
+            continue;
+        };
+        let mut points = Vec::new();
+        let mut lines = Vec::new();
+
+        gpx_parts(&geometry.value, &mut points, &mut lines);
+
+        /* waypoints must precede tracks in GPX 1.1 */
+        for point in points.iter().filter(|point| point.len() >= 2) {
+            let _ = write!(waypoints, "<wpt lat=\"{}\" lon=\"{}\">", point[1], point[0]);
+            if let Some(ele) = point.get(2) {
+                let _ = write!(waypoints, "<ele>{}</ele>", ele);
+            }
+            write_property(&mut waypoints, feature, &["name", "title"], "name");
+            write_property(&mut waypoints, feature, &["description", "desc"], "desc");
+            waypoints.push_str("</wpt>\n");
+        }
+
+        if !lines.is_empty() {
+            tracks.push_str("<trk>");
+            write_property(&mut tracks, feature, &["name", "title"], "name");
+            write_property(&mut tracks, feature, &["description", "desc"], "desc");
+            for line in lines {
+                tracks.push_str("<trkseg>");
+                line.iter()
+                    .for_each(|position| write_gpx_point(&mut tracks, "trkpt", position));
+                tracks.push_str("</trkseg>");
+            }
+            tracks.push_str("</trk>\n");
+        }
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <gpx version=\"1.1\" creator=\"megingjord\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n\
+         {}{}</gpx>\n",
+        waypoints, tracks
+    )
+}
+
+/// Color in KML "aabbggrr" form
+fn to_kml_color(color: Color32) -> String {
+    let alpha = color.a() as u32;
+    /* reverse of `Color32::gamma_multiply` used by styles, in gamma space */
+    let unmultiply = |x: u8| {
+        (x as u32 * 255 + alpha / 2)
+            .checked_div(alpha)
+            .map_or(0, |x| x.min(255))
+    };
+
+    format!(
+        "{:02x}{:02x}{:02x}{:02x}",
+        alpha,
+        unmultiply(color.b()),
+        unmultiply(color.g()),
+        unmultiply(color.r())
+    )
+}
+
+fn write_kml_coordinates(out: &mut String, positions: &[Position]) {
+    let tuples: Vec<String> = positions
+        .iter()
+        .filter(|position| position.len() >= 2)
+        .map(|position| {
+            position
+                .iter()
+                .take(3)
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .collect();
+
+    let _ = write!(out, "<coordinates>{}</coordinates>", tuples.join(" "));
+}
+
+fn write_kml_polygon(out: &mut String, rings: &[Vec<Position>]) {
+    out.push_str("<Polygon>");
+    for (idx, ring) in rings.iter().enumerate() {
+        let boundary = if idx == 0 { "outerBoundaryIs" } else { "innerBoundaryIs" };
+
+        let _ = write!(out, "<{boundary}><LinearRing>");
+        write_kml_coordinates(out, ring);
+        let _ = write!(out, "</LinearRing></{boundary}>");
+    }
+    out.push_str("</Polygon>");
+}
+
+fn write_kml_geometry(out: &mut String, value: &Value) {
+    match value {
+        Value::Point(point) => {
+            out.push_str("<Point>");
+            write_kml_coordinates(out, std::slice::from_ref(point));
+            out.push_str("</Point>");
+        }
+        Value::LineString(line) => {
+            out.push_str("<LineString>");
+            write_kml_coordinates(out, line);
+            out.push_str("</LineString>");
+        }
+        Value::Polygon(rings) => write_kml_polygon(out, rings),
+        Value::MultiPoint(points) => {
+            out.push_str("<MultiGeometry>");
+            for point in points {
+                write_kml_geometry(out, &Value::Point(point.clone()));
+            }
+            out.push_str("</MultiGeometry>");
+        }
+        Value::MultiLineString(lines) => {
+            out.push_str("<MultiGeometry>");
+            for line in lines {
+                out.push_str("<LineString>");
+                write_kml_coordinates(out, line);
+                out.push_str("</LineString>");
+            }
+            out.push_str("</MultiGeometry>");
+        }
+        Value::MultiPolygon(polygons) => {
+            out.push_str("<MultiGeometry>");
+            polygons.iter().for_each(|rings| write_kml_polygon(out, rings));
+            out.push_str("</MultiGeometry>");
+        }
+        Value::GeometryCollection(geometries) => {
+            out.push_str("<MultiGeometry>");
+            geometries
+                .iter()
+                .for_each(|geometry| write_kml_geometry(out, &geometry.value));
+            out.push_str("</MultiGeometry>");
+        }
+    }
+}
+
+fn export_kml(json: &GeoJson, name: &str) -> String {
+    let mut placemarks = String::new();
+
+    for feature in features_of(json) {
+        let Some(geometry) = &feature.geometry else {
+            continue;
+        };
+        let style = FeatureStyle::from_properties(feature.properties.as_ref());
+
+        placemarks.push_str("<Placemark>");
+        write_property(&mut placemarks, &feature, &["name", "title"], "name");
+        write_property(&mut placemarks, &feature, &["description", "desc"], "description");
+        let _ = write!(
+            placemarks,
+            "<Style><LineStyle><color>{}</color><width>{}</width></LineStyle>\
+             <PolyStyle><color>{}</color></PolyStyle><IconStyle><color>{}</color></IconStyle></Style>",
+            to_kml_color(style.stroke.color),
+            style.stroke.width,
+            to_kml_color(style.fill),
+            to_kml_color(style.marker.color)
+        );
+        write_kml_geometry(&mut placemarks, &geometry.value);
+        placemarks.push_str("</Placemark>\n");
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <kml xmlns=\"http://www.opengis.net/kml/2.2\"><Document><name>{}</name>\n{}</Document></kml>\n",
+        xml_escape(name),
+        placemarks
+    )
+}
+
+/// Content of the `format` file with the data, `name` is used as a document title when format supports it
+pub fn export(json: &GeoJson, format: FileFormat, name: &str) -> String {
+    match format {
+        FileFormat::GeoJson => json.to_string(),
+        FileFormat::Gpx => export_gpx(json),
+        FileFormat::Kml => export_kml(json, name),
+    }
+}
+
+/// File name from the layer's `name` with the format's extension
+fn file_name(name: &str, format: FileFormat) -> String {
+    let name: String = name
+        .trim()
+        .chars()
+        .map(|x| {
+            if x.is_alphanumeric() || "-_.".contains(x) {
+                x
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let name = if name.is_empty() { "layer".to_string() } else { name };
+
+    format!("{}.{}", name, format.extensions()[0])
+}
+
+/// Ask where to save the data and write it on the runtime, the UI is not blocked by the dialog, errors are logged
+#[cfg(all(not(target_arch = "wasm32"), not(target_os = "android")))]
+pub fn save(spawner: &Spawner, json: &GeoJson, format: FileFormat, name: &str) {
+    let content = export(json, format, name);
+    let picked = rfd::AsyncFileDialog::new()
+        .set_file_name(file_name(name, format))
+        .add_filter(format.name(), format.extensions())
+        .save_file();
+
+    spawner.spawn(async move {
+        let Some(file) = picked.await else {
+            return;
+        };
+        let path = file.path().to_path_buf();
+        let written = {
+            let path = path.clone();
+
+            match tokio::task::spawn_blocking(move || std::fs::write(path, content)).await {
+                Ok(written) => written.map_err(|err| err.to_string()),
+                Err(err) => Err(err.to_string()),
+            }
+        };
+
+        match written {
+            Ok(_) => log::info!("File {} saved", path.display()),
+            Err(err) => log::error!("File {} not saved: {}", path.display(), err),
+        }
+    });
+}
+
+#[cfg(target_os = "android")]
+pub fn save(_spawner: &Spawner, _json: &GeoJson, format: FileFormat, name: &str) {
+    log::error!("File {} not saved: not supported", file_name(name, format));
+}
+
+/// Time given to the browser to start the download before the object url is revoked
+#[cfg(target_arch = "wasm32")]
+const OBJECT_URL_LIFETIME_MS: i32 = 60_000;
+
+/// Offer the data to the browser as a downloaded file, errors are logged
+#[cfg(target_arch = "wasm32")]
+pub fn save(_spawner: &Spawner, json: &GeoJson, format: FileFormat, name: &str) {
+    use wasm_bindgen::closure::Closure;
+    use wasm_bindgen::JsCast;
+
+    let file_name = file_name(name, format);
+    let content = export(json, format, name);
+
+    let download = || -> Result<(), wasm_bindgen::JsValue> {
+        let window = web_sys::window().ok_or("window not acquired")?;
+        let document = window.document().ok_or("document not acquired")?;
+        let mut options = web_sys::BlobPropertyBag::new();
+
+        options.type_(format.mime_type());
+
+        let parts = js_sys::Array::of1(&wasm_bindgen::JsValue::from_str(&content));
+        let blob = web_sys::Blob::new_with_str_sequence_and_options(&parts, &options)?;
+        let url = web_sys::Url::create_object_url_with_blob(&blob)?;
+        let anchor: web_sys::HtmlAnchorElement = document.create_element("a")?.dyn_into()?;
+
+        anchor.set_href(&url);
+        anchor.set_download(&file_name);
+        anchor.click();
+
+        /* the download starts asynchronously, the url revoked right after the click may be lost */
+        let revoke = Closure::once_into_js(move || {
+            if let Err(err) = web_sys::Url::revoke_object_url(&url) {
+                log::error!("Object url {} not revoked: {:?}", url, err);
+            }
+        });
+
+        window
+            .set_timeout_with_callback_and_timeout_and_arguments_0(revoke.unchecked_ref(), OBJECT_URL_LIFETIME_MS)
+            .map(|_| ())
+    };
+
+    match download() {
+        Ok(_) => log::info!("File {} saved", file_name),
+        Err(err) => log::error!("File {} not saved: {:?}", file_name, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collection(features: Vec<Feature>) -> GeoJson {
+        GeoJson::FeatureCollection(FeatureCollection {
+            bbox: None,
+            features,
+            foreign_members: None,
+        })
+    }
+
+    fn named(value: Value, name: &str) -> Feature {
+        let mut properties = JsonObject::new();
+
+        properties.insert("name".to_string(), JsonValue::from(name));
+        feature(value, properties)
+    }
+
+    fn values(json: &GeoJson) -> Vec<Value> {
+        features_of(json)
+            .into_iter()
+            .filter_map(|feature| feature.geometry.map(|geometry| geometry.value))
+            .collect()
+    }
+
+    fn names(json: &GeoJson) -> Vec<String> {
+        features_of(json)
+            .iter()
+            .filter_map(|feature| property_text(feature, &["name"]))
+            .collect()
+    }
+
+    #[test]
+    fn gpx_waypoints_routes_and_tracks_are_imported() {
+        let gpx = "\u{feff}<?xml version=\"1.0\"?>
+            <gpx version=\"1.0\" xmlns=\"http://www.topografix.com/GPX/1/0\">
+              <wpt lat=\"60.5\" lon=\"30.25\"><ele>12</ele><name> spring </name><desc>cold</desc></wpt>
+              <wpt lat=\"broken\" lon=\"30\"/>
+              <rte><name>road</name><rtept lat=\"60\" lon=\"30\"/><rtept lat=\"61\" lon=\"31\"/></rte>
+              <rte><rtept lat=\"60\" lon=\"30\"/></rte>
+              <trk><name>walk</name>
+                <trkseg><trkpt lat=\"1\" lon=\"2\"/><trkpt lat=\"3\" lon=\"4\"/></trkseg>
+                <trkseg><trkpt lat=\"5\" lon=\"6\"/><trkpt lat=\"7\" lon=\"8\"/></trkseg>
+              </trk>
+            </gpx>";
+        let json = import("track.GPX", gpx.as_bytes()).unwrap();
+
+        assert_eq!(
+            values(&json),
+            [
+                Value::Point(vec![30.25, 60.5, 12.0]),
+                Value::LineString(vec![vec![30.0, 60.0], vec![31.0, 61.0]]),
+                Value::MultiLineString(vec![
+                    vec![vec![2.0, 1.0], vec![4.0, 3.0]],
+                    vec![vec![6.0, 5.0], vec![8.0, 7.0]]
+                ]),
+            ]
+        );
+        assert_eq!(names(&json), ["spring", "road", "walk"]);
+        assert_eq!(features_of(&json)[0].property("desc"), Some(&JsonValue::from("cold")));
+    }
+
+    #[test]
+    fn gpx_export_is_imported_back() {
+        let json = collection(vec![
+            named(Value::Point(vec![30.25, 60.5, 12.0]), "<spring> & well"),
+            named(Value::LineString(vec![vec![30.0, 60.0], vec![31.0, 61.0]]), "road"),
+            named(
+                Value::MultiLineString(vec![
+                    vec![vec![2.0, 1.0], vec![4.0, 3.0]],
+                    vec![vec![6.0, 5.0], vec![8.0, 7.0]],
+                ]),
+                "walk",
+            ),
+        ]);
+        let imported = import("export.gpx", export(&json, FileFormat::Gpx, "layer").as_bytes()).unwrap();
+
+        assert_eq!(values(&imported), values(&json));
+        assert_eq!(names(&imported), names(&json));
+    }
+
+    #[test]
+    fn gpx_polygons_are_exported_as_tracks() {
+        let ring = vec![vec![0.0, 0.0], vec![1.0, 0.0], vec![1.0, 1.0], vec![0.0, 0.0]];
+        let json = collection(vec![named(Value::Polygon(vec![ring.clone()]), "area")]);
+        let imported = import("export.gpx", export(&json, FileFormat::Gpx, "layer").as_bytes()).unwrap();
+
+        assert_eq!(values(&imported), [Value::LineString(ring)]);
+    }
+
+    #[test]
+    fn kml_placemarks_are_imported_from_folders() {
+        let kml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+            <kml xmlns=\"http://www.opengis.net/kml/2.2\"><Document><Folder>
+              <Placemark><name>spring</name>
+                <Style><IconStyle><color>ff0000ff</color></IconStyle></Style>
+                <Point><coordinates>30.25,60.5,12</coordinates></Point>
+              </Placemark>
+              <Placemark><name>lake</name>
+                <Style><LineStyle><color>7f00ff00</color><width>3</width></LineStyle>
+                  <PolyStyle><color>zz00ff00</color></PolyStyle></Style>
+                <Polygon>
+                  <outerBoundaryIs><LinearRing><coordinates>
+                    0,0 4,0 4,4 0,0
+                  </coordinates></LinearRing></outerBoundaryIs>
+                  <innerBoundaryIs><LinearRing><coordinates>1,1 2,1 2,2 1,1</coordinates></LinearRing></innerBoundaryIs>
+                  <innerBoundaryIs><LinearRing><coordinates>1,1 2,2</coordinates></LinearRing></innerBoundaryIs>
+                </Polygon>
+              </Placemark>
+              <Placemark><name>empty</name><Point><coordinates>x,y</coordinates></Point></Placemark>
+            </Folder></Document></kml>";
+        let json = import("places.kml", kml.as_bytes()).unwrap();
+        let features = features_of(&json);
+
+        assert_eq!(
+            values(&json),
+            [
+                Value::Point(vec![30.25, 60.5, 12.0]),
+                Value::Polygon(vec![
+                    vec![vec![0.0, 0.0], vec![4.0, 0.0], vec![4.0, 4.0], vec![0.0, 0.0]],
+                    vec![vec![1.0, 1.0], vec![2.0, 1.0], vec![2.0, 2.0], vec![1.0, 1.0]],
+                ]),
+            ]
+        );
+        assert_eq!(names(&json), ["spring", "lake"]);
+        assert_eq!(features[0].property("marker-color"), Some(&JsonValue::from("#ff0000")));
+        assert_eq!(features[1].property("stroke"), Some(&JsonValue::from("#00ff00")));
+        assert_eq!(features[1].property("stroke-width"), Some(&JsonValue::from(3.0)));
+        assert_eq!(features[1].property("fill"), None);
+    }
+
+    #[test]
+    fn kml_export_is_imported_back() {
+        let mut line = named(Value::LineString(vec![vec![30.0, 60.0], vec![31.0, 61.0, 5.0]]), "road");
+
+        line.set_property("stroke", "#ff0000");
+        line.set_property("stroke-opacity", 1.0);
+        line.set_property("stroke-width", 3.0);
+
+        let json = collection(vec![
+            named(Value::Point(vec![30.25, 60.5]), "<spring> & well"),
+            line,
+            named(
+                Value::Polygon(vec![
+                    vec![vec![0.0, 0.0], vec![4.0, 0.0], vec![4.0, 4.0], vec![0.0, 0.0]],
+                    vec![vec![1.0, 1.0], vec![2.0, 1.0], vec![2.0, 2.0], vec![1.0, 1.0]],
+                ]),
+                "lake",
+            ),
+            named(
+                Value::GeometryCollection(vec![
+                    Geometry::new(Value::Point(vec![1.0, 2.0])),
+                    Geometry::new(Value::LineString(vec![vec![1.0, 2.0], vec![3.0, 4.0]])),
+                ]),
+                "camp",
+            ),
+        ]);
+        let imported = import("export.kml", export(&json, FileFormat::Kml, "a & b").as_bytes()).unwrap();
+        let features = features_of(&imported);
+
+        assert_eq!(values(&imported), values(&json));
+        assert_eq!(names(&imported), names(&json));
+        assert_eq!(features[1].property("stroke"), Some(&JsonValue::from("#ff0000")));
+        assert_eq!(features[1].property("stroke-opacity"), Some(&JsonValue::from(1.0)));
+        assert_eq!(features[1].property("stroke-width"), Some(&JsonValue::from(3.0)));
+    }
+
+    #[test]
+    fn kml_multi_lines_are_imported_as_collections() {
+        let lines = vec![
+            vec![vec![0.0, 0.0], vec![1.0, 1.0]],
+            vec![vec![2.0, 2.0], vec![3.0, 3.0]],
+        ];
+        let json = collection(vec![named(Value::MultiLineString(lines.clone()), "paths")]);
+        let imported = import("export.kml", export(&json, FileFormat::Kml, "layer").as_bytes()).unwrap();
+
+        assert_eq!(
+            values(&imported),
+            [Value::GeometryCollection(
+                lines
+                    .into_iter()
+                    .map(|line| Geometry::new(Value::LineString(line)))
+                    .collect()
+            )]
+        );
+    }
+
+    #[test]
+    fn files_without_features_are_rejected() {
+        let gpx = "<gpx version=\"1.1\" xmlns=\"http://www.topografix.com/GPX/1/1\"></gpx>";
+
+        assert!(matches!(
+            import("empty.gpx", gpx.as_bytes()),
+            Err(ImportError::NoFeatures)
+        ));
+        assert!(matches!(import("broken.kml", b"<kml>"), Err(ImportError::Xml(_))));
+        assert!(matches!(import("notes.txt", b""), Err(ImportError::UnknownFormat)));
+    }
+}
@@ -0,0 +1,156 @@
+use geographiclib_rs::{Geodesic, InverseGeodesic, PolygonArea, Winding};
+use geojson::{Position, Value};
+use rstar::AABB;
+
+/// Convert GeoJSON bbox (2D or 3D) to lon/lat envelope
+pub fn bbox_to_envelope(bbox: &geojson::Bbox) -> Option<AABB<[f64; 2]>> {
+    if !matches!(bbox.len(), 4 | 6) {
+        return None;
+    }
+
+    let dimensions = bbox.len() / 2;
+
+    Some(AABB::from_corners(
+        [bbox[0], bbox[1]],
+        [bbox[dimensions], bbox[dimensions + 1]],
+    ))
+}
+
+/// Union of two bboxes of the same dimensions
+pub fn bbox_union(a: &geojson::Bbox, b: &geojson::Bbox) -> Option<geojson::Bbox> {
+    if a.len() != b.len() || !matches!(a.len(), 4 | 6) {
+        return None;
+    }
+
+    let dimensions = a.len() / 2;
+    let lower = (0..dimensions).map(|idx| a[idx].min(b[idx]));
+    let upper = (dimensions..a.len()).map(|idx| a[idx].max(b[idx]));
+
+    Some(lower.chain(upper).collect())
+}
+
+/// Call `f` for every position of the geometry
+pub fn for_each_position(value: &Value, f: &mut impl FnMut(&Position)) {
+    match value {
+        Value::Point(point) => f(point),
+        Value::MultiPoint(points) | Value::LineString(points) => points.iter().for_each(f),
+        Value::MultiLineString(lines) | Value::Polygon(lines) => lines.iter().flatten().for_each(f),
+        Value::MultiPolygon(polygons) => polygons.iter().flatten().flatten().for_each(f),
+        Value::GeometryCollection(geometries) => geometries
+            .iter()
+            .for_each(|geometry| for_each_position(&geometry.value, f)),
+    }
+}
+
+/// Envelope of the geometry in lon/lat, `None` when geometry has no positions
+pub fn envelope(value: &Value) -> Option<AABB<[f64; 2]>> {
+    let mut lower = [f64::MAX; 2];
+    let mut upper = [f64::MIN; 2];
+
+    for_each_position(value, &mut |position| {
+        if position.len() >= 2 {
+            for axis in 0..2 {
+                lower[axis] = lower[axis].min(position[axis]);
+                upper[axis] = upper[axis].max(position[axis]);
+            }
+        }
+    });
+
+    if lower[0] <= upper[0] && lower[1] <= upper[1] {
+        Some(AABB::from_corners(lower, upper))
+    } else {
+        None
+    }
+}
+
+pub fn vertex_count(value: &Value) -> usize {
+    let mut count = 0;
+
+    for_each_position(value, &mut |_| count += 1);
+    count
+}
+
+fn line_length(line: &[Position], geodesic: &Geodesic) -> f64 {
+    line.windows(2)
+        .filter(|pair| pair[0].len() >= 2 && pair[1].len() >= 2)
+        .map(|pair| -> f64 { geodesic.inverse(pair[0][1], pair[0][0], pair[1][1], pair[1][0]) })
+        .sum()
+}
+
+fn ring_area(ring: &[Position], geodesic: &Geodesic) -> f64 {
+    let mut polygon_area = PolygonArea::new(geodesic, Winding::CounterClockwise);
+
+    for position in ring.iter().filter(|position| position.len() >= 2) {
+        polygon_area.add_point(position[1], position[0]);
+    }
+
+    /* signed result to not get the area of the rest of the Earth for clockwise rings */
+    let (_, area, _) = polygon_area.compute(true);
+    area.abs()
+}
+
+fn polygon_area(rings: &[Vec<Position>], geodesic: &Geodesic) -> f64 {
+    let mut rings = rings.iter();
+    let exterior = rings.next().map_or(0.0, |ring| ring_area(ring, geodesic));
+    let holes: f64 = rings.map(|ring| ring_area(ring, geodesic)).sum();
+
+    (exterior - holes).max(0.0)
+}
+
+/// Geodesic length in meters of linear parts of the geometry
+pub fn length(value: &Value) -> f64 {
+    let geodesic = Geodesic::wgs84();
+
+    match value {
+        Value::LineString(line) => line_length(line, &geodesic),
+        Value::MultiLineString(lines) => lines.iter().map(|line| line_length(line, &geodesic)).sum(),
+        Value::GeometryCollection(geometries) => geometries.iter().map(|geometry| length(&geometry.value)).sum(),
+        _ => 0.0,
+    }
+}
+
+/// Geodesic area in square meters of polygonal parts of the geometry
+pub fn area(value: &Value) -> f64 {
+    let geodesic = Geodesic::wgs84();
+
+    match value {
+        Value::Polygon(rings) => polygon_area(rings, &geodesic),
+        Value::MultiPolygon(polygons) => polygons.iter().map(|rings| polygon_area(rings, &geodesic)).sum(),
+        Value::GeometryCollection(geometries) => geometries.iter().map(|geometry| area(&geometry.value)).sum(),
+        _ => 0.0,
+    }
+}
+
+/// Maximum zoom to fit a tiny extent like a single point
+const MAX_FIT_ZOOM: u8 = 18;
+
+/// Web Mercator `y` in range 0..1 from the north to the south
+pub fn mercator_y(lat: f64) -> f64 {
+    let lat = lat.clamp(-85.0511, 85.0511).to_radians();
+
+    0.5 - (std::f64::consts::FRAC_PI_4 + lat / 2.0).tan().ln() / (2.0 * std::f64::consts::PI)
+}
+
+pub fn mercator_lat(y: f64) -> f64 {
+    let n = std::f64::consts::PI * (1.0 - 2.0 * y);
+
+    n.sinh().atan().to_degrees()
+}
+
+/// Center and zoom level to fit `extent` into `viewport` pixels with `tile_size` pixel tiles
+pub fn fit_extent(extent: &AABB<[f64; 2]>, viewport: egui::Vec2, tile_size: f64) -> (walkers::Position, u8) {
+    let lower = extent.lower();
+    let upper = extent.upper();
+    let top = mercator_y(upper[1]);
+    let bottom = mercator_y(lower[1]);
+    let width = (upper[0] - lower[0]) / 360.0;
+    let height = bottom - top;
+
+    let zoom_x = (viewport.x as f64 / (tile_size * width)).log2();
+    let zoom_y = (viewport.y as f64 / (tile_size * height)).log2();
+    let zoom = zoom_x.min(zoom_y).floor().clamp(0.0, MAX_FIT_ZOOM as f64) as u8;
+
+    let center = walkers::Position::from_lat_lon(mercator_lat((top + bottom) / 2.0), (lower[0] + upper[0]) / 2.0);
+
+    (center, zoom)
+}
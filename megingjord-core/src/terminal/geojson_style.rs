@@ -0,0 +1,209 @@
+//! Styling of GeoJSON features with simplestyle-spec properties:
+//! https://github.com/mapbox/simplestyle-spec/tree/master/1.1.0
+use super::mappainter::Color;
+use egui::{Color32, Stroke};
+use geojson::{JsonObject, JsonValue};
+use std::fmt::Display;
+use std::str::FromStr;
+
+const DEFAULT_STROKE_COLOR: Color32 = Color32::from_rgb(0x55, 0x55, 0x55);
+const DEFAULT_STROKE_WIDTH: f32 = 2.0;
+const DEFAULT_STROKE_OPACITY: f32 = 1.0;
+const DEFAULT_FILL_COLOR: Color32 = Color32::from_rgb(0x55, 0x55, 0x55);
+const DEFAULT_FILL_OPACITY: f32 = 0.6;
+const DEFAULT_MARKER_COLOR: Color32 = Color32::from_rgb(0x7e, 0x7e, 0x7e);
+
+/// Radius of the marker in pixels for `marker-size` values
+const MARKER_SIZE_SMALL: f32 = 4.0;
+const MARKER_SIZE_MEDIUM: f32 = 6.0;
+const MARKER_SIZE_LARGE: f32 = 9.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MarkerShape {
+    #[default]
+    Circle,
+    Square,
+    Triangle,
+    Cross,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct MarkerShapeParseError;
+
+impl Display for MarkerShapeParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for MarkerShape {
+    type Err = MarkerShapeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "circle" | "circle-stroked" => Ok(Self::Circle),
+            "square" | "square-stroked" => Ok(Self::Square),
+            "triangle" | "triangle-stroked" => Ok(Self::Triangle),
+            "cross" => Ok(Self::Cross),
+            _ => Err(MarkerShapeParseError),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Marker {
+    pub shape: MarkerShape,
+    pub color: Color32,
+    pub size: f32,
+    /// Short text from `marker-symbol` like "1" or "A" to draw over the marker
+    pub label: Option<String>,
+}
+
+impl Default for Marker {
+    fn default() -> Self {
+        Self {
+            shape: Default::default(),
+            color: DEFAULT_MARKER_COLOR,
+            size: MARKER_SIZE_MEDIUM,
+            label: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FeatureStyle {
+    pub stroke: Stroke,
+    pub fill: Color32,
+    pub marker: Marker,
+}
+
+impl Default for FeatureStyle {
+    fn default() -> Self {
+        Self {
+            stroke: Stroke::new(
+                DEFAULT_STROKE_WIDTH,
+                DEFAULT_STROKE_COLOR.gamma_multiply(DEFAULT_STROKE_OPACITY),
+            ),
+            fill: DEFAULT_FILL_COLOR.gamma_multiply(DEFAULT_FILL_OPACITY),
+            marker: Default::default(),
+        }
+    }
+}
+
+fn value_to_color(value: &JsonValue) -> Option<Color32> {
+    let hex = value.as_str()?.trim().trim_start_matches('#');
+
+    let hex = match hex.len() {
+        /* short form: "#rgb" */
+        3 => hex.chars().flat_map(|x| [x, x]).collect(),
+        6 => hex.to_string(),
+        _ => return None,
+    };
+
+    if !hex.chars().all(|x| x.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    format!("#{}", hex).parse::<Color>().ok().map(|x| x.to_color32())
+}
+
+/// Numbers are accepted both as JSON numbers and strings
+fn value_to_number(value: &JsonValue) -> Option<f32> {
+    let number = match value {
+        JsonValue::Number(number) => number.as_f64(),
+        JsonValue::String(string) => string.trim().parse::<f64>().ok(),
+        _ => None,
+    }? as f32;
+
+    if number.is_finite() {
+        Some(number)
+    } else {
+        None
+    }
+}
+
+fn value_to_opacity(value: &JsonValue) -> Option<f32> {
+    value_to_number(value).map(|x| x.clamp(0.0, 1.0))
+}
+
+fn value_to_width(value: &JsonValue) -> Option<f32> {
+    value_to_number(value).filter(|x| *x >= 0.0)
+}
+
+fn value_to_marker_size(value: &JsonValue) -> Option<f32> {
+    match value.as_str() {
+        Some("small") => Some(MARKER_SIZE_SMALL),
+        Some("medium") => Some(MARKER_SIZE_MEDIUM),
+        Some("large") => Some(MARKER_SIZE_LARGE),
+        _ => value_to_number(value).filter(|x| *x > 0.0),
+    }
+}
+
+/// Find first property from `keys` with the value accepted by `convert`
+fn lookup<T>(properties: &JsonObject, keys: &[&str], convert: fn(&JsonValue) -> Option<T>) -> Option<T> {
+    keys.iter().find_map(|key| properties.get(*key).and_then(convert))
+}
+
+impl Marker {
+    fn from_properties(properties: &JsonObject) -> Self {
+        let default = Self::default();
+        let mut marker = Self {
+            color: lookup(properties, &["marker-color", "color"], value_to_color).unwrap_or(default.color),
+            size: lookup(properties, &["marker-size"], value_to_marker_size).unwrap_or(default.size),
+            ..default
+        };
+
+        if let Some(symbol) = properties.get("marker-symbol").and_then(|x| x.as_str()) {
+            match symbol.parse::<MarkerShape>() {
+                Ok(shape) => marker.shape = shape,
+                /* simplestyle allows numbers and letters as symbols */
+                Err(_) if !symbol.is_empty() && symbol.chars().count() <= 2 => marker.label = Some(symbol.to_string()),
+                /* other maki icons are not supported */
+                Err(_) => {}
+            }
+        }
+
+        marker
+    }
+}
+
+impl FeatureStyle {
+    /// Style from simplestyle-spec properties, `color` and `width` are used as fallbacks
+    /// for data written by the painter. Malformed values are replaced with defaults.
+    pub fn from_properties(properties: Option<&JsonObject>) -> Self {
+        let Some(properties) = properties else {
+            return Self::default();
+        };
+
+        let stroke_color = lookup(properties, &["stroke", "color"], value_to_color).unwrap_or(DEFAULT_STROKE_COLOR);
+        let stroke_opacity =
+            lookup(properties, &["stroke-opacity"], value_to_opacity).unwrap_or(DEFAULT_STROKE_OPACITY);
+        let stroke_width =
+            lookup(properties, &["stroke-width", "width"], value_to_width).unwrap_or(DEFAULT_STROKE_WIDTH);
+        let fill_color = lookup(properties, &["fill"], value_to_color).unwrap_or(DEFAULT_FILL_COLOR);
+        let fill_opacity = lookup(properties, &["fill-opacity"], value_to_opacity).unwrap_or(DEFAULT_FILL_OPACITY);
+
+        Self {
+            stroke: Stroke::new(stroke_width, stroke_color.gamma_multiply(stroke_opacity)),
+            fill: fill_color.gamma_multiply(fill_opacity),
+            marker: Marker::from_properties(properties),
+        }
+    }
+
+    /// Apply layer settings: `color` replaces colors keeping their opacity, `opacity` fades the whole style
+    pub fn with_layer(mut self, color: Option<Color32>, opacity: f32) -> Self {
+        let apply = |part: Color32| {
+            let part = match color {
+                Some(color) => color.gamma_multiply(part.a() as f32 / 255.0),
+                None => part,
+            };
+
+            part.gamma_multiply(opacity)
+        };
+
+        self.stroke.color = apply(self.stroke.color);
+        self.fill = apply(self.fill);
+        self.marker.color = apply(self.marker.color);
+        self
+    }
+}
@@ -0,0 +1,369 @@
+//! Live updates of waist channels over Server-Sent Events
+use super::network::Spawner;
+use reqwest::Client;
+
+/// Names of events sent by waist's `/subscribe/:id`
+const EVENT_FEATURES: &str = "features";
+const EVENT_DELETED: &str = "deleted";
+const EVENT_RESYNC: &str = "resync";
+
+#[derive(Debug)]
+pub enum SubscriptionEvent {
+    /// Connection is established, `reconnect` is set when updates may be missed since the previous connection
+    Connected {
+        reconnect: bool,
+    },
+    Disconnected,
+    /// Features added to the channel as FeatureCollection JSON
+    Features(String),
+    /// Features deleted from the channel as JSON array of their ids
+    Deleted(String),
+    /// Server dropped some updates, the channel must be downloaded again
+    Resync,
+}
+
+/// Parser of `text/event-stream` for events known by the client
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+struct EventParser {
+    buffer: Vec<u8>,
+    event: String,
+    data: String,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl EventParser {
+    fn feed(&mut self, chunk: &[u8]) -> Vec<SubscriptionEvent> {
+        let mut events = Vec::new();
+
+        self.buffer.extend_from_slice(chunk);
+
+        while let Some(end) = self.buffer.iter().position(|x| *x == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            /* empty line completes the event */
+            if line.is_empty() {
+                events.extend(self.dispatch());
+                continue;
+            }
+
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+                None => (line, ""),
+            };
+
+            match field {
+                "event" => self.event = value.to_string(),
+                "data" => {
+                    if !self.data.is_empty() {
+                        self.data.push('\n');
+                    }
+                    self.data.push_str(value);
+                }
+                /* comments used as keep-alive, ids and retry hints */
+                _ => {}
+            }
+        }
+
+        events
+    }
+
+    fn dispatch(&mut self) -> Option<SubscriptionEvent> {
+        let event = std::mem::take(&mut self.event);
+        let data = std::mem::take(&mut self.data);
+
+        match event.as_str() {
+            EVENT_FEATURES => Some(SubscriptionEvent::Features(data)),
+            EVENT_DELETED => Some(SubscriptionEvent::Deleted(data)),
+            EVENT_RESYNC => Some(SubscriptionEvent::Resync),
+            _ => None,
+        }
+    }
+}
+
+/// Delay before the reconnection, doubled after each failed attempt
+const RECONNECT_DELAY: f64 = 1.0;
+const RECONNECT_DELAY_MAX: f64 = 60.0;
+
+/// Delay in seconds before the reconnection after `attempts` failed ones
+fn reconnect_delay(attempts: i32) -> f64 {
+    (RECONNECT_DELAY * 2f64.powi(attempts)).min(RECONNECT_DELAY_MAX)
+}
+
+/// Subscription to the channel, stopped when dropped
+pub struct Subscription {
+    #[cfg(not(target_arch = "wasm32"))]
+    abort: futures::future::AbortHandle,
+    #[cfg(target_arch = "wasm32")]
+    state: Option<SharedEventSourceState>,
+}
+
+#[cfg(target_arch = "wasm32")]
+type SharedEventSourceState = std::rc::Rc<std::cell::RefCell<EventSourceState>>;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Subscription {
+    /// Listen to events of `url` and pass them to `on_event`, connection is restored until dropped
+    pub fn new(
+        spawner: &Spawner,
+        client: Client,
+        url: String,
+        token: Option<String>,
+        on_event: impl FnMut(SubscriptionEvent) + Send + 'static,
+    ) -> Self {
+        let (abort, registration) = futures::future::AbortHandle::new_pair();
+        let future = futures::future::Abortable::new(Self::run(client, url, token, on_event), registration);
+
+        spawner.spawn(async move {
+            let _ = future.await;
+        });
+
+        Self { abort }
+    }
+
+    async fn run(client: Client, url: String, token: Option<String>, mut on_event: impl FnMut(SubscriptionEvent)) {
+        let mut attempts = 0;
+        let mut connected_before = false;
+
+        loop {
+            let mut request = client.get(&url).header(reqwest::header::ACCEPT, "text/event-stream");
+
+            if let Some(token) = &token {
+                request = request.bearer_auth(token);
+            }
+
+            let response = request.send().await;
+
+            match response {
+                Ok(mut response) if response.status() == reqwest::StatusCode::OK => {
+                    log::info!("Subscribed to {}", url);
+                    attempts = 0;
+                    on_event(SubscriptionEvent::Connected {
+                        reconnect: connected_before,
+                    });
+                    connected_before = true;
+
+                    let mut parser = EventParser::default();
+
+                    loop {
+                        match response.chunk().await {
+                            Ok(Some(chunk)) => parser.feed(&chunk).into_iter().for_each(&mut on_event),
+                            Ok(None) => {
+                                log::info!("Subscription {} closed by server", url);
+                                break;
+                            }
+                            Err(err) => {
+                                log::warn!("Subscription {} interrupted: {}", url, err);
+                                break;
+                            }
+                        }
+                    }
+
+                    on_event(SubscriptionEvent::Disconnected);
+                }
+                Ok(response) => log::warn!("Subscription {} refused: {}", url, response.status()),
+                Err(err) => log::warn!("Subscription {} failed: {}", url, err),
+            }
+
+            let delay = reconnect_delay(attempts);
+
+            attempts = (attempts + 1).min(16);
+            tokio::time::sleep(std::time::Duration::from_secs_f64(delay)).await;
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.abort.abort();
+    }
+}
+
+/// Connection of the browser's EventSource, it is closed on errors and opened again with backoff:
+/// the browser gives up on HTTP errors and retries network errors without a delay
+#[cfg(target_arch = "wasm32")]
+struct EventSourceState {
+    url: String,
+    token: Option<String>,
+    on_event: Box<dyn FnMut(SubscriptionEvent)>,
+    source: Option<web_sys::EventSource>,
+    listeners: Vec<wasm_bindgen::closure::Closure<dyn FnMut(web_sys::Event)>>,
+    /// Failed connections since the last established one
+    attempts: i32,
+    connected: bool,
+    connected_before: bool,
+    /// `setTimeout` handle of the scheduled reconnection
+    timeout: Option<i32>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Subscription {
+    /// Listen to events of `url` and pass them to `on_event`, connection is restored until dropped.
+    /// EventSource can not send headers, the token is passed in the query
+    pub fn new(
+        _spawner: &Spawner,
+        _client: Client,
+        url: String,
+        token: Option<String>,
+        on_event: impl FnMut(SubscriptionEvent) + Send + 'static,
+    ) -> Self {
+        let state = std::rc::Rc::new(std::cell::RefCell::new(EventSourceState {
+            url,
+            token,
+            on_event: Box::new(on_event),
+            source: None,
+            listeners: Vec::new(),
+            attempts: 0,
+            connected: false,
+            connected_before: false,
+            timeout: None,
+        }));
+
+        Self::connect(&state);
+
+        Self { state: Some(state) }
+    }
+
+    fn connect(state: &SharedEventSourceState) {
+        use wasm_bindgen::JsCast;
+
+        let mut inner = state.borrow_mut();
+
+        inner.timeout = None;
+
+        let url = match &inner.token {
+            Some(token) => format!(
+                "{}?access_token={}",
+                inner.url,
+                String::from(js_sys::encode_uri_component(token))
+            ),
+            None => inner.url.clone(),
+        };
+        let source = match web_sys::EventSource::new(&url) {
+            Ok(source) => source,
+            Err(err) => {
+                log::error!("Subscription {} failed: {:?}", inner.url, err);
+                return;
+            }
+        };
+        let mut listeners = Vec::new();
+
+        Self::listen(state, &source, &mut listeners, "open", |state, _| {
+            let mut inner = state.borrow_mut();
+            let reconnect = inner.connected_before;
+
+            log::info!("Subscribed to {}", inner.url);
+            inner.attempts = 0;
+            inner.connected = true;
+            inner.connected_before = true;
+            (inner.on_event)(SubscriptionEvent::Connected { reconnect });
+        });
+        Self::listen(state, &source, &mut listeners, "error", |state, _| {
+            let mut inner = state.borrow_mut();
+
+            log::warn!("Subscription {} interrupted", inner.url);
+            if let Some(source) = inner.source.take() {
+                source.close();
+            }
+            if inner.connected {
+                inner.connected = false;
+                (inner.on_event)(SubscriptionEvent::Disconnected);
+            }
+            drop(inner);
+            Self::reconnect_later(state);
+        });
+        for name in [EVENT_FEATURES, EVENT_DELETED] {
+            Self::listen(state, &source, &mut listeners, name, move |state, event| {
+                let Some(data) = event
+                    .dyn_ref::<web_sys::MessageEvent>()
+                    .and_then(|event| event.data().as_string())
+                else {
+                    return;
+                };
+                let event = match name {
+                    EVENT_FEATURES => SubscriptionEvent::Features(data),
+                    _ => SubscriptionEvent::Deleted(data),
+                };
+
+                (state.borrow_mut().on_event)(event);
+            });
+        }
+        Self::listen(state, &source, &mut listeners, EVENT_RESYNC, |state, _| {
+            (state.borrow_mut().on_event)(SubscriptionEvent::Resync)
+        });
+
+        /* previous listeners are not running: connection is restored by the timeout */
+        inner.source = Some(source);
+        inner.listeners = listeners;
+    }
+
+    /// Add the listener of the source's events, it holds a weak reference: the state owns it
+    fn listen(
+        state: &SharedEventSourceState,
+        source: &web_sys::EventSource,
+        listeners: &mut Vec<wasm_bindgen::closure::Closure<dyn FnMut(web_sys::Event)>>,
+        name: &str,
+        mut handler: impl FnMut(&SharedEventSourceState, web_sys::Event) + 'static,
+    ) {
+        use wasm_bindgen::closure::Closure;
+        use wasm_bindgen::JsCast;
+
+        let state = std::rc::Rc::downgrade(state);
+        let listener = Closure::<dyn FnMut(web_sys::Event)>::new(move |event: web_sys::Event| {
+            if let Some(state) = state.upgrade() {
+                handler(&state, event);
+            }
+        });
+
+        if let Err(err) = source.add_event_listener_with_callback(name, listener.as_ref().unchecked_ref()) {
+            log::error!("Subscription '{}' listener not added: {:?}", name, err);
+        }
+        listeners.push(listener);
+    }
+
+    fn reconnect_later(state: &SharedEventSourceState) {
+        use wasm_bindgen::closure::Closure;
+        use wasm_bindgen::JsCast;
+
+        let mut inner = state.borrow_mut();
+        let delay = reconnect_delay(inner.attempts);
+        let weak = std::rc::Rc::downgrade(state);
+        let callback = Closure::once_into_js(move || {
+            if let Some(state) = weak.upgrade() {
+                Self::connect(&state);
+            }
+        });
+
+        inner.attempts = (inner.attempts + 1).min(16);
+        match web_sys::window().map(|window| {
+            window.set_timeout_with_callback_and_timeout_and_arguments_0(
+                callback.unchecked_ref(),
+                (delay * 1000.0) as i32,
+            )
+        }) {
+            Some(Ok(timeout)) => inner.timeout = Some(timeout),
+            Some(Err(err)) => log::error!("Subscription {} not restored: {:?}", inner.url, err),
+            None => log::error!("websys: window not acquired"),
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let Some(state) = self.state.take() else {
+            return;
+        };
+        let mut inner = state.borrow_mut();
+
+        if let Some(source) = inner.source.take() {
+            source.close();
+        }
+        if let (Some(timeout), Some(window)) = (inner.timeout.take(), web_sys::window()) {
+            window.clear_timeout_with_handle(timeout);
+        }
+    }
+}
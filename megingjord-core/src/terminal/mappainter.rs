@@ -1,3 +1,5 @@
+use super::geojson_file::{self, FileFormat};
+use super::network::Spawner;
 use egui::{Align2, Area, Color32, Key, Painter, Response, RichText, Ui, Window};
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
@@ -15,8 +17,9 @@ impl Point {
         Self(other.lat(), other.lon())
     }
 
+    /// GeoJSON position: longitude first, then latitude (RFC 7946)
     fn to_geo_vec2(self) -> Vec<f64> {
-        [self.0, self.1].to_vec()
+        [self.1, self.0].to_vec()
     }
 }
 
@@ -69,8 +72,21 @@ impl std::str::FromStr for Color {
     }
 }
 
+/// Random id of a new line, the line's feature keeps it in exports
+fn new_line_id() -> String {
+    let mut bytes = [0u8; 8];
+
+    if let Err(err) = getrandom::getrandom(&mut bytes) {
+        log::error!("Line id not generated: {}", err);
+    }
+    format!("{:016x}", u64::from_le_bytes(bytes))
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct DrawedLine {
+    /// Given once, lines saved before ids get new ones
+    #[serde(default = "new_line_id")]
+    id: String,
     color: Color,
     points: Vec<Point>,
 }
@@ -78,6 +94,7 @@ struct DrawedLine {
 impl Default for DrawedLine {
     fn default() -> Self {
         Self {
+            id: new_line_id(),
             points: Vec::new(),
             color: Color::from_color32(egui::Color32::RED),
         }
@@ -85,8 +102,10 @@ impl Default for DrawedLine {
 }
 
 impl DrawedLine {
+    /// Start the next line, it gets a new id
     fn clear(&mut self) {
-        self.points.clear()
+        self.points.clear();
+        self.id = new_line_id();
     }
 
     fn in_bbox(&self, bbox: &BoundaryBox) -> bool {
@@ -103,6 +122,20 @@ impl DrawedLine {
             self.points.iter().map(|x| x.to_geo_vec2()).collect(),
         ))
     }
+
+    fn to_feature(&self) -> geojson::Feature {
+        let mut properties = geojson::JsonObject::new();
+        properties.insert(String::from("color"), geojson::JsonValue::from(self.color.to_string()));
+        properties.insert(String::from("width"), geojson::JsonValue::from(2));
+
+        geojson::Feature {
+            bbox: None,
+            geometry: Some(self.to_geometry()),
+            id: Some(geojson::feature::Id::String(self.id.clone())),
+            properties: Some(properties),
+            foreign_members: None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -126,12 +159,6 @@ struct MapPainter {
     export: Vec<geojson::GeoJson>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
-struct BBoxedLines {
-    bbox: BoundaryBox,
-    lines: Vec<DrawedLine>,
-}
-
 impl MapPainter {
     fn apply_state_json(state_json: Option<String>) -> PainterLines {
         if let Some(state_json) = state_json {
@@ -167,18 +194,8 @@ impl MapPainter {
         let mut to_remove = Vec::new();
 
         for (idx, line) in self.lines.completed.iter().enumerate() {
-            let mut properties = geojson::JsonObject::new();
-            properties.insert(String::from("color"), geojson::JsonValue::from(line.color.to_string()));
-            properties.insert(String::from("width"), geojson::JsonValue::from(2));
-
             if line.in_bbox(&bbox) {
-                features.push(geojson::Feature {
-                    bbox: None,
-                    geometry: Some(line.to_geometry()),
-                    id: None,
-                    properties: Some(properties),
-                    foreign_members: None,
-                });
+                features.push(line.to_feature());
                 to_remove.push(idx);
             }
         }
@@ -194,6 +211,15 @@ impl MapPainter {
         }
     }
 
+    /// All completed lines, the painter is not changed
+    fn sketch(&self) -> geojson::FeatureCollection {
+        geojson::FeatureCollection {
+            bbox: None,
+            features: self.lines.completed.iter().map(|line| line.to_feature()).collect(),
+            foreign_members: None,
+        }
+    }
+
     fn set_color(&mut self, color: Color) {
         self.current.color = color;
     }
@@ -353,7 +379,7 @@ impl MapPainterPlugin {
         }
     }
 
-    fn show_ui_edit(&mut self, ui: &Ui) {
+    fn show_ui_edit(&mut self, ui: &Ui, spawner: &Spawner) {
         let (painting_mode, has_lines, has_forward_history) = {
             (
                 self.painter.painting_mode_enabled,
@@ -384,8 +410,20 @@ impl MapPainterPlugin {
                             self.painter.export.push(figures.into());
                         }
                     }
+                    ui.add_space(8.0);
+                    ui.menu_button(RichText::new("E").heading(), |ui| {
+                        for format in FileFormat::ALL {
+                            if ui.button(format.name()).clicked() {
+                                geojson_file::save(spawner, &self.painter.sketch().into(), format, "sketch");
+                                ui.close_menu();
+                            }
+                        }
+                    })
+                    .response
+                    .on_hover_text("Export sketch");
                 } else {
-                    ui.add_space(BUTTON_SIZE.x);
+                    /* place of the send and export buttons */
+                    ui.add_space(BUTTON_SIZE.x * 2.0 + 8.0);
                 }
                 if painting_mode {
                     ui.add_space(8.0);
@@ -454,7 +492,7 @@ impl MapPainterPlugin {
         }
     }
 
-    pub fn show_ui(&mut self, ui: &Ui) {
+    pub fn show_ui(&mut self, ui: &Ui, spawner: &Spawner) {
         let painting_mode = self.painter.painting_mode_enabled;
 
         Window::new("Painter")
@@ -476,7 +514,7 @@ impl MapPainterPlugin {
             self.show_ui_palette(ui);
         }
 
-        self.show_ui_edit(ui);
+        self.show_ui_edit(ui, spawner);
     }
 
     pub fn painting_in_progress(&self) -> bool {
@@ -505,11 +543,12 @@ impl BoundaryBox {
         (a.0 > point.0 && a.1 < point.1) && (c.0 < point.0 && c.1 > point.1)
     }
 
+    /// GeoJSON bbox: west, south, east, north (RFC 7946)
     fn to_geo_vec4(self) -> Vec<f64> {
         let a = self.0;
         let c = self.1;
 
-        [a.0, a.1, c.0, c.1].to_vec()
+        [a.1, c.0, c.1, a.0].to_vec()
     }
 }
 
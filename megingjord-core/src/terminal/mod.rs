@@ -1,8 +1,15 @@
+pub mod channel_cache;
 pub mod config;
 pub mod geojson_dispatcher;
+pub mod geojson_file;
+pub mod geojson_geometry;
+pub mod geojson_style;
+pub mod geojson_subscription;
 pub mod geolocation;
 pub mod local_osm_tiles;
 pub mod mappainter;
+pub mod network;
+pub mod vector_tiles;
 
 use core::cell::Cell;
 use egui::Align2;
@@ -75,10 +82,12 @@ fn sources(egui_ctx: Context) -> (HashMap<Source, Box<dyn TilesManager + Send>>,
 }
 
 /// Wasm32 window.location.href info
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct UrlHashInfo {
     position: Position,
     zoom: u8,
+    /// Channel opened by the link, "#map=zoom/lat/lon&channel=id"
+    channel: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -91,11 +100,16 @@ impl FromStr for UrlHashInfo {
         let mut zoom: u8 = 0;
         let mut lat: f64 = 0.;
         let mut lon: f64 = 0.;
+        let (map, channel) = match instr.split_once("&channel=") {
+            Some((map, channel)) => (map, Some(channel.to_string()).filter(|x| !x.is_empty())),
+            None => (instr, None),
+        };
 
-        scanf::sscanf!(instr, "#map={}/{}/{}", zoom, lat, lon).map_err(|_| UrlHashParseError)?;
+        scanf::sscanf!(map, "#map={}/{}/{}", zoom, lat, lon).map_err(|_| UrlHashParseError)?;
         Ok(Self {
             position: Position::from_lat_lon(lat, lon),
             zoom,
+            channel,
         })
     }
 }
@@ -105,6 +119,7 @@ impl Default for UrlHashInfo {
         Self {
             position: Position::from_lat_lon(0.0, 0.0),
             zoom: 0,
+            channel: None,
         }
     }
 }
@@ -117,10 +132,23 @@ impl std::fmt::Display for UrlHashInfo {
             self.zoom,
             self.position.lat(),
             self.position.lon()
-        )
+        )?;
+
+        if let Some(channel) = &self.channel {
+            write!(f, "&channel={}", channel)?;
+        }
+        Ok(())
     }
 }
 
+/// Names of server profiles added at start
+#[cfg(not(target_arch = "wasm32"))]
+const SERVER_PROFILE_COMMAND_LINE: &str = "command_line";
+#[cfg(target_arch = "wasm32")]
+const SERVER_PROFILE_ORIGIN: &str = "origin";
+#[cfg(target_arch = "wasm32")]
+const SERVER_PROFILE_QUERY: &str = "query";
+
 pub struct MyApp {
     sources: HashMap<Source, Box<dyn TilesManager + Send>>,
     selected_source: Source,
@@ -161,28 +189,101 @@ impl MyApp {
             instance.map_memory.center_at(lat_lon.to_position());
         }
 
+        instance.apply_servers(config.servers.unwrap_or_default(), config.server);
+
+        if let Some(channels) = config.channels {
+            instance.geojson_dispatcher.set_channels(channels);
+        }
+
+        match config.layers {
+            Some(layers) => instance.geojson_dispatcher.restore_layers(&layers),
+            None => instance
+                .geojson_dispatcher
+                .open_channel(geojson_dispatcher::DEFAULT_CHANNEL),
+        }
+
+        if let Some(uploads) = config.uploads {
+            instance.geojson_dispatcher.restore_uploads(&uploads);
+        }
+
+        /* channel of the link is opened after restored layers to not open it twice */
         #[cfg(target_arch = "wasm32")]
         {
             instance.update_from_hash();
             instance.watch_geolocation();
         }
 
-        instance.geojson_dispatcher.download("world".to_string());
         instance
     }
 
+    /// Select waist server from config, command line (native) or page's location (wasm32)
+    fn apply_servers(&mut self, mut servers: config::ServerProfiles, mut server: Option<String>) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let mut args = std::env::args().skip_while(|arg| arg != "--server").skip(1);
+
+            if let Some(url) = args.next() {
+                match servers.insert(SERVER_PROFILE_COMMAND_LINE, &url) {
+                    Ok(_) => server = Some(SERVER_PROFILE_COMMAND_LINE.to_string()),
+                    Err(err) => log::warn!("--server is ignored: {}", err),
+                }
+            }
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        let location = web_sys::window().map(|window| window.location());
+
+        #[cfg(target_arch = "wasm32")]
+        if let Some(location) = &location {
+            let url = location
+                .search()
+                .ok()
+                .and_then(|search| web_sys::UrlSearchParams::new_with_str(&search).ok())
+                .and_then(|params| params.get("server"));
+
+            if let Some(url) = url {
+                match servers.insert(SERVER_PROFILE_QUERY, &url) {
+                    Ok(_) => server = Some(SERVER_PROFILE_QUERY.to_string()),
+                    Err(err) => log::warn!("?server is ignored: {}", err),
+                }
+            }
+        }
+
+        if servers.get(geojson_dispatcher::DEFAULT_SERVER_NAME).is_none() {
+            servers
+                .insert(
+                    geojson_dispatcher::DEFAULT_SERVER_NAME,
+                    geojson_dispatcher::DEFAULT_SERVER_URL,
+                )
+                .unwrap();
+        }
+
+        self.geojson_dispatcher.set_servers(servers, server);
+
+        /* the page's host is usually a static one, it becomes a profile only when it serves waist too */
+        #[cfg(target_arch = "wasm32")]
+        if let Some(origin) = location.and_then(|location| location.origin().ok()) {
+            if origin.starts_with("http") {
+                self.geojson_dispatcher.probe_server(SERVER_PROFILE_ORIGIN, &origin);
+            }
+        }
+    }
+
     /// Set browser's url hash
     #[cfg(target_arch = "wasm32")]
     fn update_hash(&mut self, position: Position, zoom: u8) -> bool {
-        let href = UrlHashInfo { position, zoom };
+        let href = UrlHashInfo {
+            position,
+            zoom,
+            channel: self.href.channel.clone(),
+        };
 
         if href != self.href {
-            self.href = href;
-
             let _ = web_sys::window().map(|window| {
                 let _ = window.location().set_hash(format!("{}", href).as_str());
                 true
             });
+            self.href = href;
         }
         false
     }
@@ -196,6 +297,9 @@ impl MyApp {
                     if href != self.href {
                         self.map_memory.center_at(href.position);
                         self.zoom_to(href.zoom);
+                        if let Some(channel) = &href.channel {
+                            self.geojson_dispatcher.open_channel(channel);
+                        }
                         self.href = href
                     }
                 }
@@ -226,17 +330,42 @@ impl MyApp {
         self.geo.lock().unwrap().get()
     }
 
+    fn my_position(&self) -> Position {
+        match self.probe_geolocation() {
+            Some(geolocation) => geolocation.position,
+            None => Position::from_lat_lon(0.0, 0.0),
+        }
+    }
+
+    fn config(&self, center: Position) -> config::Config {
+        config::Config {
+            lat_lon: Some(config::Position::from_position(center)),
+            zoom: Some(self.map_memory.zoom_get()),
+            state: self.plugin_painter.get_state_json(),
+            server: Some(self.geojson_dispatcher.server().to_string()),
+            servers: Some(self.geojson_dispatcher.servers().clone()),
+            uploads: self.geojson_dispatcher.pending_uploads(),
+            layers: self.geojson_dispatcher.layers(),
+            channels: Some(self.geojson_dispatcher.channels().clone()),
+        }
+    }
+
     fn zoom_to(&mut self, zoom_value: u8) {
-        while self.map_memory.zoom_get() < zoom_value {
-            if self.map_memory.zoom_in().is_err() {
-                break;
-            }
+        zoom_to(&mut self.map_memory, zoom_value);
+    }
+}
+
+/// Step map's zoom until `zoom_value` or the nearest supported level
+pub fn zoom_to(map_memory: &mut MapMemory, zoom_value: u8) {
+    while map_memory.zoom_get() < zoom_value {
+        if map_memory.zoom_in().is_err() {
+            break;
         }
+    }
 
-        while self.map_memory.zoom_get() > zoom_value {
-            if self.map_memory.zoom_out().is_err() {
-                break;
-            }
+    while map_memory.zoom_get() > zoom_value {
+        if map_memory.zoom_out().is_err() {
+            break;
         }
     }
 }
@@ -304,13 +433,11 @@ impl eframe::App for MyApp {
         };
 
         let geolocation = self.probe_geolocation();
-        let myposition = if let Some(geolocation) = geolocation {
-            geolocation.position
-        } else {
-            Position::from_lat_lon(0.0, 0.0)
-        };
+        let myposition = self.my_position();
         let center = self.map_memory.detached().unwrap_or(myposition);
 
+        self.geojson_dispatcher.poll(ctx);
+
         CentralPanel::default().frame(rimless).show(ctx, |ui| {
             let tiles = self.sources.get_mut(&self.selected_source).unwrap().as_mut();
             let attribution = tiles.attribution();
@@ -322,7 +449,7 @@ impl eframe::App for MyApp {
                 .drag_gesture(!self.plugin_painter.painting_in_progress())
                 .with_plugin(&mut self.plugin_painter)
                 .with_plugin(geolocation::GeoLocationPlugin::new(geolocation))
-                .with_plugin(&self.geojson_dispatcher);
+                .with_plugin(&mut self.geojson_dispatcher);
 
             ui.add(map);
 
@@ -336,20 +463,31 @@ impl eframe::App for MyApp {
                     controls(ui, &mut self.selected_source, &mut self.sources.keys());
                 }
                 acknowledge(ui, attribution);
-                self.geojson_dispatcher.show_ui(ui);
+                self.geojson_dispatcher.show_ui(ui, &mut self.map_memory);
                 geolocation::GeoLocationPlugin::show_ui(ui, &mut self.map_memory, geolocation, center);
             }
-            self.plugin_painter.show_ui(ui);
+            self.plugin_painter.show_ui(ui, self.geojson_dispatcher.spawner());
         });
 
         #[cfg(target_arch = "wasm32")]
         {
             self.update_hash(center, self.map_memory.zoom_get());
         }
-        self.config_ctx.config_update(
-            self.map_memory.zoom_get(),
-            Some(config::Position::from_position(center)),
-            self.plugin_painter.get_state_json(),
-        );
+        /* serialized layers and uploads may be large, they are built only when the config is saved */
+        if self.config_ctx.config_due() {
+            let config = self.config(center);
+
+            self.config_ctx.config_save(config);
+        }
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.geojson_dispatcher.shutdown();
+
+        /* changes made since the last periodic save are not lost */
+        let center = self.map_memory.detached().unwrap_or(self.my_position());
+        let config = self.config(center);
+
+        self.config_ctx.config_save(config);
     }
 }
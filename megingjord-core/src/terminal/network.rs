@@ -0,0 +1,100 @@
+//! Shared runtime of the client's network requests
+use std::future::Future;
+
+/// Future run by the network runtime, native futures run on the runtime's threads
+#[cfg(not(target_arch = "wasm32"))]
+pub trait NetworkFuture: Future<Output = ()> + Send + 'static {}
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: Future<Output = ()> + Send + 'static> NetworkFuture for T {}
+#[cfg(target_arch = "wasm32")]
+pub trait NetworkFuture: Future<Output = ()> + 'static {}
+#[cfg(target_arch = "wasm32")]
+impl<T: Future<Output = ()> + 'static> NetworkFuture for T {}
+
+/// Requests mostly wait for the network, few threads are enough
+#[cfg(not(target_arch = "wasm32"))]
+const WORKER_THREADS: usize = 2;
+
+/// Time given to running requests to stop at shutdown
+#[cfg(not(target_arch = "wasm32"))]
+const SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Owner of the runtime, spawned futures are stopped when it is shut down or dropped
+pub struct Network {
+    #[cfg(not(target_arch = "wasm32"))]
+    runtime: Option<tokio::runtime::Runtime>,
+    spawner: Spawner,
+}
+
+/// Handle to spawn futures on the network runtime
+#[derive(Clone)]
+pub struct Spawner {
+    #[cfg(not(target_arch = "wasm32"))]
+    handle: tokio::runtime::Handle,
+}
+
+impl Network {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new() -> Self {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(WORKER_THREADS)
+            .thread_name("network")
+            .enable_all()
+            .build()
+            .unwrap();
+        let spawner = Spawner {
+            handle: runtime.handle().clone(),
+        };
+
+        Self {
+            runtime: Some(runtime),
+            spawner,
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn new() -> Self {
+        Self { spawner: Spawner {} }
+    }
+
+    pub fn spawner(&self) -> &Spawner {
+        &self.spawner
+    }
+
+    /// Stop spawned futures, futures spawned later are dropped right away
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn shutdown(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            log::info!("Network runtime is shutting down");
+            runtime.shutdown_timeout(SHUTDOWN_TIMEOUT);
+        }
+    }
+
+    /// Futures of the page are stopped with the page
+    #[cfg(target_arch = "wasm32")]
+    pub fn shutdown(&mut self) {}
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Network {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl Spawner {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn spawn(&self, future: impl NetworkFuture) {
+        self.handle.spawn(future);
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn spawn(&self, future: impl NetworkFuture) {
+        wasm_bindgen_futures::spawn_local(future);
+    }
+}
@@ -0,0 +1,634 @@
+//! Mapbox Vector Tiles of waist channels, decoded into GeoJSON features:
+//! https://github.com/mapbox/vector-tile-spec/tree/master/2.1
+use super::geojson_geometry::{mercator_lat, mercator_y};
+use geojson::{Feature, Geometry, JsonObject, JsonValue, Value};
+use rstar::AABB;
+use std::collections::HashMap;
+
+/// Deepest zoom of requested tiles, deeper zooms show these tiles scaled, waist's `MAX_ZOOM` must be the same
+pub const MAX_TILE_ZOOM: u8 = 16;
+
+/// Tiles kept in memory per layer, the least recently shown ones are evicted first
+const TILE_CACHE_CAPACITY: usize = 256;
+
+/// Visible tiles requested at once, the view is tiled at a lower zoom when there are more
+const MAX_VISIBLE_TILES: usize = 64;
+
+const GEOM_POINT: u64 = 1;
+const GEOM_LINESTRING: u64 = 2;
+const GEOM_POLYGON: u64 = 3;
+
+const CMD_MOVE_TO: u32 = 1;
+const CMD_LINE_TO: u32 = 2;
+const CMD_CLOSE_PATH: u32 = 7;
+
+const DEFAULT_EXTENT: u32 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileId {
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileId {
+    /// Lon/lat envelope of the tile
+    pub fn envelope(&self) -> AABB<[f64; 2]> {
+        let scale = (1u64 << self.z) as f64;
+        let lon = |x: f64| x / scale * 360.0 - 180.0;
+        let lat = |y: f64| mercator_lat(y / scale);
+
+        AABB::from_corners(
+            [lon(self.x as f64), lat(self.y as f64 + 1.0)],
+            [lon(self.x as f64 + 1.0), lat(self.y as f64)],
+        )
+    }
+
+    /// The tile and tiles of lower zooms containing it, from the deepest one
+    pub fn ancestors(&self) -> impl Iterator<Item = TileId> {
+        let tile = *self;
+
+        (0..=tile.z).rev().map(move |z| {
+            let shift = tile.z - z;
+
+            TileId {
+                z,
+                x: tile.x >> shift,
+                y: tile.y >> shift,
+            }
+        })
+    }
+
+    /// Lon/lat position of the point in tile coordinates
+    fn position(&self, point: (i64, i64), extent: u32) -> geojson::Position {
+        let scale = (1u64 << self.z) as f64;
+        let x = self.x as f64 + point.0 as f64 / extent as f64;
+        let y = self.y as f64 + point.1 as f64 / extent as f64;
+
+        vec![x / scale * 360.0 - 180.0, mercator_lat(y / scale)]
+    }
+}
+
+/// Zoom of tiles shown `width` pixels wide over `area` with `tile_size` pixel map tiles
+pub fn tile_zoom(area: &AABB<[f64; 2]>, width: f32, tile_size: f64) -> u8 {
+    let span = (area.upper()[0] - area.lower()[0]) / 360.0;
+
+    if span <= 0.0 {
+        return MAX_TILE_ZOOM;
+    }
+
+    (width as f64 / (tile_size * span))
+        .log2()
+        .floor()
+        .clamp(0.0, MAX_TILE_ZOOM as f64) as u8
+}
+
+/// Tiles of zoom `z` covering `area`, lower zoom is used when there are too many of them
+pub fn covering(area: &AABB<[f64; 2]>, z: u8) -> Vec<TileId> {
+    let mut z = z;
+
+    loop {
+        let scale = 1u32 << z;
+        let column = |lon: f64| (((lon + 180.0) / 360.0 * scale as f64).floor().max(0.0) as u32).min(scale - 1);
+        let row = |lat: f64| ((mercator_y(lat) * scale as f64).floor().max(0.0) as u32).min(scale - 1);
+        let (west, east) = (column(area.lower()[0]), column(area.upper()[0]));
+        let (north, south) = (row(area.upper()[1]), row(area.lower()[1]));
+        let count = (east - west + 1) as usize * (south - north + 1) as usize;
+
+        if count <= MAX_VISIBLE_TILES || z == 0 {
+            return (north..=south)
+                .flat_map(|y| (west..=east).map(move |x| TileId { z, x, y }))
+                .collect();
+        }
+        z -= 1;
+    }
+}
+
+/// Protobuf message reader
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+/// Field's value by the wire type
+enum Field<'a> {
+    Varint(u64),
+    Fixed64([u8; 8]),
+    Bytes(&'a [u8]),
+    Fixed32([u8; 4]),
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn take(&mut self, size: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(size).filter(|x| *x <= self.data.len());
+        let end = end.ok_or_else(|| "unexpected end of message".to_string())?;
+        let bytes = &self.data[self.pos..end];
+
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("varint is too long".to_string())
+    }
+
+    /// Next field number with its value, `None` at the end of the message
+    fn field(&mut self) -> Result<Option<(u32, Field<'a>)>, String> {
+        if self.pos >= self.data.len() {
+            return Ok(None);
+        }
+
+        let key = self.varint()?;
+        let value = match key & 0x7 {
+            0 => Field::Varint(self.varint()?),
+            1 => Field::Fixed64(self.take(8)?.try_into().unwrap()),
+            2 => {
+                let size = self.varint()? as usize;
+
+                Field::Bytes(self.take(size)?)
+            }
+            5 => Field::Fixed32(self.take(4)?.try_into().unwrap()),
+            wire_type => return Err(format!("unsupported wire type {}", wire_type)),
+        };
+
+        Ok(Some(((key >> 3) as u32, value)))
+    }
+}
+
+/// Values of the repeated integer field, packed or not
+fn push_packed(values: &mut Vec<u32>, field: Field) -> Result<(), String> {
+    match field {
+        Field::Varint(value) => values.push(value as u32),
+        Field::Bytes(bytes) => {
+            let mut reader = Reader::new(bytes);
+
+            while reader.pos < bytes.len() {
+                values.push(reader.varint()? as u32);
+            }
+        }
+        _ => return Err("repeated field of unexpected type".to_string()),
+    }
+    Ok(())
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn decode_value(data: &[u8]) -> Result<JsonValue, String> {
+    let mut reader = Reader::new(data);
+    let mut value = JsonValue::Null;
+
+    while let Some((number, field)) = reader.field()? {
+        value = match (number, field) {
+            (1, Field::Bytes(bytes)) => JsonValue::from(String::from_utf8_lossy(bytes).to_string()),
+            (2, Field::Fixed32(bytes)) => JsonValue::from(f32::from_le_bytes(bytes) as f64),
+            (3, Field::Fixed64(bytes)) => JsonValue::from(f64::from_le_bytes(bytes)),
+            (4, Field::Varint(int)) => JsonValue::from(int as i64),
+            (5, Field::Varint(uint)) => JsonValue::from(uint),
+            (6, Field::Varint(sint)) => JsonValue::from(unzigzag(sint)),
+            (7, Field::Varint(boolean)) => JsonValue::from(boolean != 0),
+            _ => value,
+        };
+    }
+    Ok(value)
+}
+
+/// Doubled signed area, positive for exterior rings in tile coordinates
+fn ring_area(ring: &[(i64, i64)]) -> i64 {
+    (0..ring.len())
+        .map(|i| {
+            let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum()
+}
+
+/// Lines of the geometry commands, rings are not closed
+fn decode_commands(commands: &[u32]) -> Result<Vec<Vec<(i64, i64)>>, String> {
+    let mut lines: Vec<Vec<(i64, i64)>> = Vec::new();
+    let mut cursor = (0i64, 0i64);
+    let mut iter = commands.iter();
+
+    while let Some(command) = iter.next() {
+        let (id, count) = (command & 0x7, command >> 3);
+
+        match id {
+            CMD_MOVE_TO | CMD_LINE_TO => {
+                for _ in 0..count {
+                    let (Some(dx), Some(dy)) = (iter.next(), iter.next()) else {
+                        return Err("truncated geometry".to_string());
+                    };
+
+                    cursor = (cursor.0 + unzigzag(*dx as u64), cursor.1 + unzigzag(*dy as u64));
+                    match lines.last_mut() {
+                        Some(line) if id == CMD_LINE_TO => line.push(cursor),
+                        _ => lines.push(vec![cursor]),
+                    }
+                }
+            }
+            CMD_CLOSE_PATH => {}
+            id => return Err(format!("unknown geometry command {}", id)),
+        }
+    }
+    Ok(lines)
+}
+
+fn decode_geometry(tile: &TileId, kind: u64, commands: &[u32], extent: u32) -> Result<Option<Value>, String> {
+    let lines = decode_commands(commands)?;
+    let to_positions =
+        |line: &[(i64, i64)]| -> Vec<geojson::Position> { line.iter().map(|x| tile.position(*x, extent)).collect() };
+
+    let value = match kind {
+        GEOM_POINT => {
+            let mut points: Vec<geojson::Position> = lines.iter().flat_map(|x| to_positions(x)).collect();
+
+            match points.len() {
+                0 => None,
+                1 => points.pop().map(Value::Point),
+                _ => Some(Value::MultiPoint(points)),
+            }
+        }
+        GEOM_LINESTRING => {
+            let mut lines: Vec<_> = lines.iter().filter(|x| x.len() > 1).map(|x| to_positions(x)).collect();
+
+            match lines.len() {
+                0 => None,
+                1 => lines.pop().map(Value::LineString),
+                _ => Some(Value::MultiLineString(lines)),
+            }
+        }
+        GEOM_POLYGON => {
+            let mut polygons: Vec<Vec<Vec<geojson::Position>>> = Vec::new();
+
+            for ring in lines.iter().filter(|x| x.len() > 2) {
+                let area = ring_area(ring);
+                let mut positions = to_positions(ring);
+
+                /* GeoJSON rings are closed */
+                positions.push(positions[0].clone());
+
+                match polygons.last_mut() {
+                    Some(polygon) if area < 0 => polygon.push(positions),
+                    _ if area > 0 => polygons.push(vec![positions]),
+                    /* degenerate ring or a hole without the exterior */
+                    _ => {}
+                }
+            }
+
+            match polygons.len() {
+                0 => None,
+                1 => polygons.pop().map(Value::Polygon),
+                _ => Some(Value::MultiPolygon(polygons)),
+            }
+        }
+        _ => None,
+    };
+
+    Ok(value)
+}
+
+fn decode_feature(
+    tile: &TileId,
+    data: &[u8],
+    keys: &[String],
+    values: &[JsonValue],
+    extent: u32,
+) -> Result<Option<Feature>, String> {
+    let mut reader = Reader::new(data);
+    let mut id = None;
+    let mut tags = Vec::new();
+    let mut kind = 0;
+    let mut commands = Vec::new();
+
+    while let Some((number, field)) = reader.field()? {
+        match (number, field) {
+            (1, Field::Varint(value)) => id = Some(value),
+            (2, field) => push_packed(&mut tags, field)?,
+            (3, Field::Varint(value)) => kind = value,
+            (4, field) => push_packed(&mut commands, field)?,
+            _ => {}
+        }
+    }
+
+    let Some(value) = decode_geometry(tile, kind, &commands, extent)? else {
+        return Ok(None);
+    };
+
+    let mut properties = JsonObject::new();
+
+    for tag in tags.chunks_exact(2) {
+        if let (Some(key), Some(value)) = (keys.get(tag[0] as usize), values.get(tag[1] as usize)) {
+            properties.insert(key.clone(), value.clone());
+        }
+    }
+
+    Ok(Some(Feature {
+        bbox: None,
+        geometry: Some(Geometry::new(value)),
+        id: id.map(|x| geojson::feature::Id::Number(x.into())),
+        properties: Some(properties),
+        foreign_members: None,
+    }))
+}
+
+fn decode_layer(tile: &TileId, data: &[u8]) -> Result<Vec<Feature>, String> {
+    let mut reader = Reader::new(data);
+    let mut features = Vec::new();
+    let mut keys = Vec::new();
+    let mut values = Vec::new();
+    let mut extent = DEFAULT_EXTENT;
+
+    /* features refer to keys and values which may follow them */
+    while let Some((number, field)) = reader.field()? {
+        match (number, field) {
+            (2, Field::Bytes(bytes)) => features.push(bytes),
+            (3, Field::Bytes(bytes)) => keys.push(String::from_utf8_lossy(bytes).to_string()),
+            (4, Field::Bytes(bytes)) => values.push(decode_value(bytes)?),
+            (5, Field::Varint(value)) if value > 0 => extent = value as u32,
+            _ => {}
+        }
+    }
+
+    let mut decoded = Vec::new();
+
+    for feature in features {
+        decoded.extend(decode_feature(tile, feature, &keys, &values, extent)?);
+    }
+    Ok(decoded)
+}
+
+/// Features of all layers of the tile with lon/lat positions, empty data is an empty tile
+pub fn decode(tile: &TileId, data: &[u8]) -> Result<Vec<Feature>, String> {
+    let mut reader = Reader::new(data);
+    let mut features = Vec::new();
+
+    while let Some((number, field)) = reader.field()? {
+        if let (3, Field::Bytes(layer)) = (number, field) {
+            features.extend(decode_layer(tile, layer)?);
+        }
+    }
+    Ok(features)
+}
+
+/// Tile of the layer's cache
+#[derive(Default)]
+pub struct CachedTile {
+    pub features: Vec<Feature>,
+    /// Features are received at least once
+    pub received: bool,
+    /// Request of the tile is running
+    pub loading: bool,
+    /// Layer is changed since the tile was received, it is shown until the fresh copy arrives
+    stale: bool,
+    pub error: Option<String>,
+    /// Frame the tile was shown last time
+    used: u64,
+}
+
+/// Received tiles of the layer with least recently used eviction
+#[derive(Default)]
+pub struct TileCache {
+    tiles: HashMap<TileId, CachedTile>,
+    frame: u64,
+}
+
+impl TileCache {
+    /// Start the next frame, tiles shown by `show` before are candidates for eviction
+    pub fn next_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Mark visible tiles as used and return ones to request, they are marked as loading
+    pub fn show(&mut self, tiles: &[TileId]) -> Vec<TileId> {
+        let mut requests = Vec::new();
+
+        for tile in tiles {
+            let cached = self.tiles.entry(*tile).or_insert_with(|| CachedTile {
+                stale: true,
+                ..Default::default()
+            });
+
+            cached.used = self.frame;
+            if cached.stale && !cached.loading {
+                cached.loading = true;
+                requests.push(*tile);
+            }
+        }
+
+        self.evict();
+        requests
+    }
+
+    pub fn get(&self, tile: &TileId) -> Option<&CachedTile> {
+        self.tiles.get(tile)
+    }
+
+    /// The tile or its nearest parent which is received, it is shown in place of the tile
+    pub fn received(&self, tile: &TileId) -> Option<(TileId, &CachedTile)> {
+        tile.ancestors().find_map(|x| {
+            self.tiles
+                .get(&x)
+                .filter(|cached| cached.received)
+                .map(|cached| (x, cached))
+        })
+    }
+
+    /// Store the answer for the requested tile, evicted tiles are not stored again
+    pub fn set(&mut self, tile: &TileId, result: Result<Vec<Feature>, String>) {
+        if let Some(cached) = self.tiles.get_mut(tile) {
+            cached.loading = false;
+            match result {
+                Ok(features) => {
+                    cached.features = features;
+                    cached.received = true;
+                    cached.stale = false;
+                    cached.error = None;
+                }
+                Err(error) => {
+                    /* not requested again until the layer is reloaded */
+                    cached.stale = false;
+                    cached.error = Some(error);
+                }
+            }
+        }
+    }
+
+    /// Request tiles again when they are shown, current data is kept until then
+    pub fn invalidate(&mut self) {
+        self.tiles.values_mut().for_each(|tile| tile.stale = true);
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.tiles.values().any(|tile| tile.loading)
+    }
+
+    /// First error of the shown tiles
+    pub fn error(&self) -> Option<&str> {
+        self.tiles
+            .values()
+            .filter(|tile| tile.used == self.frame)
+            .find_map(|tile| tile.error.as_deref())
+    }
+
+    /// Features of tiles shown in the current frame
+    pub fn feature_count(&self) -> usize {
+        self.tiles
+            .values()
+            .filter(|tile| tile.used == self.frame)
+            .map(|tile| tile.features.len())
+            .sum()
+    }
+
+    /// Drop the least recently shown tiles over the capacity, tiles of the current frame are kept
+    fn evict(&mut self) {
+        if self.tiles.len() <= TILE_CACHE_CAPACITY {
+            return;
+        }
+
+        let mut unused: Vec<(u64, TileId)> = self
+            .tiles
+            .iter()
+            .filter(|(_, tile)| tile.used != self.frame)
+            .map(|(id, tile)| (tile.used, *id))
+            .collect();
+
+        unused.sort_unstable_by_key(|(used, _)| *used);
+        for (_, id) in unused.into_iter().take(self.tiles.len() - TILE_CACHE_CAPACITY) {
+            self.tiles.remove(&id);
+        }
+    }
+}
+
+/* waist's encoder to decode tiles as they are served */
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "../../../waist/src/mvt.rs"]
+mod mvt;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decoded positions are rounded to tile coordinates
+    const TOLERANCE: f64 = 0.1;
+
+    fn feature(id: u64, value: Value, properties: JsonObject) -> Feature {
+        Feature {
+            bbox: None,
+            geometry: Some(Geometry::new(value)),
+            id: Some(geojson::feature::Id::Number(id.into())),
+            properties: Some(properties),
+            foreign_members: None,
+        }
+    }
+
+    fn is_close(a: &[f64], b: &[f64]) -> bool {
+        (a[0] - b[0]).abs() < TOLERANCE && (a[1] - b[1]).abs() < TOLERANCE
+    }
+
+    /// Tile of `features` encoded by waist and decoded back
+    fn round_trip(features: &[Feature]) -> Vec<Feature> {
+        let data = mvt::encode(&mvt::TileId::new(1, 1, 0).unwrap(), "layer", features);
+
+        decode(&TileId { z: 1, x: 1, y: 0 }, &data).unwrap()
+    }
+
+    #[test]
+    fn zoom_limit_is_shared_with_waist() {
+        assert_eq!(mvt::MAX_ZOOM, MAX_TILE_ZOOM);
+    }
+
+    #[test]
+    fn point_keeps_id_and_properties() {
+        let mut properties = JsonObject::new();
+
+        properties.insert("name".to_string(), JsonValue::from("point"));
+        properties.insert("count".to_string(), JsonValue::from(-3));
+        properties.insert("ratio".to_string(), JsonValue::from(0.5));
+        properties.insert("flag".to_string(), JsonValue::from(true));
+
+        let decoded = round_trip(&[feature(7, Value::Point(vec![100.0, 60.0]), properties.clone())]);
+
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].id, Some(geojson::feature::Id::Number(7.into())));
+        assert_eq!(decoded[0].properties, Some(properties));
+        match &decoded[0].geometry.as_ref().unwrap().value {
+            Value::Point(point) => assert!(is_close(point, &[100.0, 60.0])),
+            other => panic!("unexpected geometry {:?}", other),
+        }
+    }
+
+    #[test]
+    fn line_keeps_order_of_positions() {
+        /* the last segment goes back: deltas are negative */
+        let line = vec![vec![10.0, 10.0], vec![50.0, 40.0], vec![20.0, 30.0], vec![15.0, 5.0]];
+        let decoded = round_trip(&[feature(8, Value::LineString(line.clone()), JsonObject::new())]);
+
+        assert_eq!(decoded.len(), 1);
+        match &decoded[0].geometry.as_ref().unwrap().value {
+            Value::LineString(positions) => {
+                assert_eq!(positions.len(), line.len());
+                assert!(positions.iter().zip(&line).all(|(a, b)| is_close(a, b)));
+            }
+            other => panic!("unexpected geometry {:?}", other),
+        }
+    }
+
+    #[test]
+    fn polygon_keeps_holes_of_any_winding() {
+        let square = |min: f64, max: f64| {
+            vec![
+                vec![min, min],
+                vec![max, min],
+                vec![max, max],
+                vec![min, max],
+                vec![min, min],
+            ]
+        };
+        let reversed = |ring: Vec<Vec<f64>>| ring.into_iter().rev().collect::<Vec<_>>();
+        let polygons = [
+            vec![square(10.0, 40.0), square(20.0, 30.0)],
+            vec![square(10.0, 40.0), reversed(square(20.0, 30.0))],
+            vec![reversed(square(10.0, 40.0)), square(20.0, 30.0)],
+        ];
+
+        for polygon in polygons {
+            let decoded = round_trip(&[feature(9, Value::Polygon(polygon.clone()), JsonObject::new())]);
+
+            assert_eq!(decoded.len(), 1);
+            match &decoded[0].geometry.as_ref().unwrap().value {
+                Value::Polygon(rings) => {
+                    assert_eq!(rings.len(), 2);
+                    for (ring, expected) in rings.iter().zip(&polygon) {
+                        assert_eq!(ring.len(), expected.len());
+                        assert_eq!(ring.first(), ring.last());
+                        assert!(expected.iter().all(|x| ring.iter().any(|y| is_close(x, y))));
+                    }
+                }
+                other => panic!("unexpected geometry {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn features_outside_of_tile_are_skipped() {
+        let decoded = round_trip(&[feature(10, Value::Point(vec![-100.0, 60.0]), JsonObject::new())]);
+
+        assert!(decoded.is_empty());
+    }
+}
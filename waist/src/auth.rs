@@ -0,0 +1,225 @@
+//! API tokens and access of clients to channels
+use axum::http::{header, HeaderMap, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use derivative::Derivative;
+use sqlx::SqlitePool;
+
+/// Any channel in lists of channels
+pub const ANY_CHANNEL: &str = "*";
+
+/// Query parameter of the token for clients which can not set headers
+const ACCESS_TOKEN_PARAM: &str = "access_token";
+
+/// Access to a channel, writing allows reading too
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Read,
+    Write,
+}
+
+impl Access {
+    /// Access as it is stored in the `tokens` table
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "read" => Some(Self::Read),
+            "write" => Some(Self::Write),
+            _ => None,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct TokenConfig {
+    pub token: String,
+    pub access: Access,
+    /// Channel ids, "*" for any channel
+    pub channels: Vec<String>,
+}
+
+#[derive(Derivative, serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[derivative(Default)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Channels readable without a token, "*" for any channel
+    #[derivative(Default(value = r#"[ANY_CHANNEL.to_string()].to_vec()"#))]
+    pub anonymous_read: Vec<String>,
+    /// Channels writable without a token, "*" for any channel: servers without the `auth` section
+    /// accept anonymous writes as before tokens
+    #[derivative(Default(value = r#"[ANY_CHANNEL.to_string()].to_vec()"#))]
+    pub anonymous_write: Vec<String>,
+    /// Tokens in addition to the ones of the `tokens` table
+    pub tokens: Vec<TokenConfig>,
+}
+
+fn matches(channels: &[String], channel: &str) -> bool {
+    channels.iter().any(|x| x == ANY_CHANNEL || x == channel)
+}
+
+/// Reason of the refused request
+#[derive(Debug)]
+pub enum Denied {
+    /// No token or the token is unknown, and anonymous access is not enough
+    Unauthorized,
+    /// The token does not grant the access
+    Forbidden,
+    Failed(sqlx::Error),
+}
+
+impl IntoResponse for Denied {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                [
+                    (header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"),
+                    (header::WWW_AUTHENTICATE, "Bearer"),
+                ],
+            )
+                .into_response(),
+            Self::Forbidden => (StatusCode::FORBIDDEN, [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")]).into_response(),
+            Self::Failed(e) => {
+                tracing::error!("Token not checked: {:?}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
+                )
+                    .into_response()
+            }
+        }
+    }
+}
+
+/// Token of `Authorization: Bearer <token>`
+pub fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "))
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+}
+
+/// Uri with the token of the query hidden, for logs
+pub fn redacted_uri(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.to_string();
+    };
+    let query: Vec<String> = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((ACCESS_TOKEN_PARAM, _)) => format!("{}=REDACTED", ACCESS_TOKEN_PARAM),
+            _ => pair.to_string(),
+        })
+        .collect();
+
+    format!("{}?{}", uri.path(), query.join("&"))
+}
+
+/// Grants of the token by `tokens` table rows
+#[derive(sqlx::FromRow)]
+struct TokenRow {
+    channel: String,
+    access: String,
+}
+
+/// Check that `token` or anonymous client has `required` access to the channel,
+/// unknown tokens get anonymous access
+pub async fn authorize(
+    pool: &SqlitePool,
+    config: &AuthConfig,
+    token: Option<&str>,
+    channel: &str,
+    required: Access,
+) -> Result<(), Denied> {
+    let anonymous = if matches(&config.anonymous_write, channel) {
+        Some(Access::Write)
+    } else if matches(&config.anonymous_read, channel) {
+        Some(Access::Read)
+    } else {
+        None
+    };
+
+    if anonymous.is_some_and(|access| access >= required) {
+        return Ok(());
+    }
+
+    let Some(token) = token else {
+        return Err(Denied::Unauthorized);
+    };
+
+    let rows: Vec<TokenRow> = sqlx::query_as("SELECT channel, access FROM tokens WHERE token = $1")
+        .bind(token)
+        .fetch_all(pool)
+        .await
+        .map_err(Denied::Failed)?;
+    let configured: Vec<&TokenConfig> = config.tokens.iter().filter(|x| x.token == token).collect();
+
+    if rows.is_empty() && configured.is_empty() {
+        return Err(Denied::Unauthorized);
+    }
+
+    let granted = rows
+        .iter()
+        .filter(|row| row.channel == ANY_CHANNEL || row.channel == channel)
+        .filter_map(|row| Access::parse(&row.access))
+        .chain(
+            configured
+                .iter()
+                .filter(|x| matches(&x.channels, channel))
+                .map(|x| x.access),
+        )
+        .max();
+
+    match granted {
+        Some(access) if access >= required => Ok(()),
+        _ => Err(Denied::Forbidden),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_state;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn servers_without_auth_section_accept_anonymous_writes() {
+        let state = test_state().await;
+        let config: AuthConfig = toml::from_str("").unwrap();
+
+        assert!(authorize(&state.sqlite, &config, None, "world", Access::Write)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn configured_tokens_grant_writes() {
+        let state = test_state().await;
+        let config: AuthConfig = toml::from_str(
+            r#"
+            anonymous_write = []
+
+            [[tokens]]
+            token = "secret"
+            access = "write"
+            channels = ["team"]
+            "#,
+        )
+        .unwrap();
+
+        assert!(authorize(&state.sqlite, &config, None, "team", Access::Read)
+            .await
+            .is_ok());
+        assert!(authorize(&state.sqlite, &config, None, "team", Access::Write)
+            .await
+            .is_err());
+        assert!(authorize(&state.sqlite, &config, Some("secret"), "team", Access::Write)
+            .await
+            .is_ok());
+        assert!(
+            authorize(&state.sqlite, &config, Some("secret"), "world", Access::Write)
+                .await
+                .is_err()
+        );
+    }
+}
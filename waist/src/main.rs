@@ -2,9 +2,10 @@ use axum::{
     extract,
     extract::DefaultBodyLimit,
     handler::Handler,
-    http::header,
-    response::IntoResponse,
-    routing::{get, options},
+    http::{header, HeaderMap, StatusCode},
+    response::sse::{Event, KeepAlive, Sse},
+    response::{IntoResponse, Response},
+    routing::{get, options, post},
     Router,
 };
 pub use axum_macros::debug_handler;
@@ -12,25 +13,153 @@ use derivative::Derivative;
 use geojson::GeoJson;
 use sqlx::migrate::MigrateDatabase;
 use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::convert::Infallible;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
 use tokio_stream::StreamExt;
 use tower_http::trace;
 use tower_http::{compression::CompressionLayer, limit::RequestBodyLimitLayer};
 use tracing::Level;
 
+mod auth;
+mod mvt;
+mod retention;
+
 type SharedServerState = Arc<RwLock<ServerState>>;
 
+/// `PRAGMA user_version` of the database with positions in RFC 7946 lon/lat order
+const SCHEMA_VERSION_LON_LAT: i64 = 1;
+
+const COMMAND_MIGRATE_AXIS_ORDER: &str = "migrate-axis-order";
+
+/// Channel of `POST /new` and of features stored before channels
+const DEFAULT_CHANNEL: &str = "world";
+
+/// Updates of a channel kept for its slow subscribers, they are asked to resync when it is exceeded
+const UPDATES_CAPACITY: usize = 64;
+
+/// Foreign members of `/get/:id` answers: the cursor of the answer,
+/// ids of features deleted after the requested cursor and the number of the channel's features
+const MEMBER_CURSOR: &str = "cursor";
+const MEMBER_DELETED: &str = "deleted";
+const MEMBER_COUNT: &str = "count";
+
+/// Names of events sent to subscribers
+const EVENT_FEATURES: &str = "features";
+const EVENT_DELETED: &str = "deleted";
+const EVENT_RESYNC: &str = "resync";
+
+fn swap_position_axes(position: &mut geojson::Position) {
+    if position.len() >= 2 {
+        position.swap(0, 1);
+    }
+}
+
+fn swap_bbox_axes(bbox: &mut geojson::Bbox) {
+    /* [x1, y1, x2, y2] or [x1, y1, z1, x2, y2, z2] */
+    let dimensions = bbox.len() / 2;
+
+    if dimensions >= 2 {
+        bbox.swap(0, 1);
+        bbox.swap(dimensions, dimensions + 1);
+    }
+}
+
+fn swap_geometry_axes(geometry: &mut geojson::Geometry) {
+    if let Some(bbox) = &mut geometry.bbox {
+        swap_bbox_axes(bbox);
+    }
+
+    match &mut geometry.value {
+        geojson::Value::Point(point) => swap_position_axes(point),
+        geojson::Value::MultiPoint(points) | geojson::Value::LineString(points) => {
+            points.iter_mut().for_each(swap_position_axes)
+        }
+        geojson::Value::MultiLineString(lines) | geojson::Value::Polygon(lines) => {
+            lines.iter_mut().flatten().for_each(swap_position_axes)
+        }
+        geojson::Value::MultiPolygon(polygons) => polygons.iter_mut().flatten().flatten().for_each(swap_position_axes),
+        geojson::Value::GeometryCollection(geometries) => geometries.iter_mut().for_each(swap_geometry_axes),
+    }
+}
+
+fn swap_feature_axes(feature: &mut geojson::Feature) {
+    if let Some(bbox) = &mut feature.bbox {
+        swap_bbox_axes(bbox);
+    }
+    if let Some(geometry) = &mut feature.geometry {
+        swap_geometry_axes(geometry);
+    }
+}
+
+/// Change of the channel sent to its subscribers
+#[derive(Clone, Debug)]
+struct ChannelUpdate {
+    channel: String,
+    /// `EVENT_FEATURES` with FeatureCollection JSON or `EVENT_DELETED` with JSON array of features' ids
+    event: &'static str,
+    data: String,
+}
+
+impl ChannelUpdate {
+    fn features(channel: &str, features: Vec<geojson::Feature>) -> Self {
+        Self {
+            channel: channel.to_string(),
+            event: EVENT_FEATURES,
+            data: geojson::FeatureCollection {
+                bbox: None,
+                features,
+                foreign_members: None,
+            }
+            .to_string(),
+        }
+    }
+
+    fn deleted(channel: &str, id: Option<geojson::feature::Id>) -> Self {
+        Self {
+            channel: channel.to_string(),
+            event: EVENT_DELETED,
+            data: geojson::JsonValue::Array(id.map(id_to_json).into_iter().collect()).to_string(),
+        }
+    }
+}
+
+fn id_to_json(id: geojson::feature::Id) -> geojson::JsonValue {
+    match id {
+        geojson::feature::Id::String(id) => geojson::JsonValue::from(id),
+        geojson::feature::Id::Number(id) => geojson::JsonValue::Number(id),
+    }
+}
+
+/// Stored row of the feature
+#[derive(sqlx::FromRow)]
+struct FeatureRow {
+    rowid: i64,
+    channel: String,
+    /// The row is a tombstone of the deleted feature
+    deleted: bool,
+    json: sqlx::types::JsonValue,
+}
+
 struct ServerState {
     json: Option<GeoJson>,
     sqlite: SqlitePool,
+    /// Updates of the channels with subscribers, a channel is dropped when its last subscriber leaves
+    updates: std::sync::Mutex<HashMap<String, broadcast::Sender<ChannelUpdate>>>,
+    retention: retention::RetentionConfig,
+    auth: auth::AuthConfig,
+    /// Stored features are in legacy lat/lon order, writes are refused until they are converted:
+    /// the conversion would swap new lon/lat features too
+    legacy_axis_order: bool,
 }
 
 impl ServerState {
-    async fn create_db(db_url: &String) -> SqlitePool {
-        if !sqlx::Sqlite::database_exists(&db_url).await.unwrap_or(false) {
-            match sqlx::Sqlite::create_database(&db_url).await {
+    async fn create_db(db_url: &str) -> SqlitePool {
+        if !sqlx::Sqlite::database_exists(db_url).await.unwrap_or(false) {
+            match sqlx::Sqlite::create_database(db_url).await {
                 Ok(_) => tracing::info!("Database created sucessfully"),
                 Err(e) => panic!("{}", e),
             }
@@ -38,9 +167,13 @@ impl ServerState {
         Self::build_db_schema(db_url).await
     }
 
-    async fn build_db_schema(db_url: &String) -> SqlitePool {
+    async fn build_db_schema(db_url: &str) -> SqlitePool {
         let instance = SqlitePool::connect(db_url).await.unwrap();
-        let qry = "CREATE TABLE IF NOT EXISTS lines (timestamp DATETIME, json TEXT);";
+        let qry = format!(
+            "CREATE TABLE IF NOT EXISTS lines (timestamp DATETIME, json TEXT, channel TEXT NOT NULL DEFAULT '{}', \
+             fid INTEGER, deleted INTEGER NOT NULL DEFAULT 0);",
+            DEFAULT_CHANNEL
+        );
         let result = sqlx::query(&qry).execute(&instance).await;
 
         match result {
@@ -49,17 +182,472 @@ impl ServerState {
             }
             Err(e) => panic!("{}", e),
         }
+        Self::add_channel_column(&instance).await;
+        Self::add_feature_id_columns(&instance).await;
+        sqlx::query("CREATE INDEX IF NOT EXISTS lines_channel ON lines (channel, timestamp);")
+            .execute(&instance)
+            .await
+            .unwrap();
+        sqlx::query("CREATE INDEX IF NOT EXISTS lines_fid ON lines (fid);")
+            .execute(&instance)
+            .await
+            .unwrap();
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS tokens (token TEXT NOT NULL, channel TEXT NOT NULL, \
+             access TEXT NOT NULL CHECK (access IN ('read', 'write')), PRIMARY KEY (token, channel));",
+        )
+        .execute(&instance)
+        .await
+        .unwrap();
+        Self::create_bbox_index(&instance).await;
+        Self::check_axis_order(&instance).await;
         instance
     }
 
-    async fn new(db_url: &String) -> Self {
-        let db_url = String::from(format!("sqlite://{}", db_url));
+    /// Stable ids of features kept by their replacements and tombstones, stored features get ids of their rows
+    async fn add_feature_id_columns(pool: &SqlitePool) {
+        let exists: bool =
+            sqlx::query_scalar("SELECT COUNT(*) > 0 FROM pragma_table_info('lines') WHERE name = 'fid';")
+                .fetch_one(pool)
+                .await
+                .unwrap();
+
+        if exists {
+            return;
+        }
+
+        let mut transaction = pool.begin().await.unwrap();
+
+        for qry in [
+            "ALTER TABLE lines ADD COLUMN fid INTEGER;",
+            "ALTER TABLE lines ADD COLUMN deleted INTEGER NOT NULL DEFAULT 0;",
+            "UPDATE lines SET fid = rowid;",
+        ] {
+            sqlx::query(qry).execute(&mut *transaction).await.unwrap();
+        }
+
+        transaction.commit().await.unwrap();
+        tracing::info!("Stored features got stable ids");
+    }
+
+    /// R*Tree of features' envelopes by rows' ids, filled from stored features when it is created
+    async fn create_bbox_index(pool: &SqlitePool) {
+        let exists: bool = sqlx::query_scalar("SELECT COUNT(*) > 0 FROM sqlite_master WHERE name = 'lines_bbox';")
+            .fetch_one(pool)
+            .await
+            .unwrap();
+
+        if exists {
+            return;
+        }
+
+        let mut transaction = pool.begin().await.unwrap();
+
+        sqlx::query("CREATE VIRTUAL TABLE lines_bbox USING rtree(id, min_lon, max_lon, min_lat, max_lat);")
+            .execute(&mut *transaction)
+            .await
+            .unwrap();
+
+        let rows: Vec<(i64, String)> = sqlx::query_as("SELECT rowid, json FROM lines;")
+            .fetch_all(&mut *transaction)
+            .await
+            .unwrap();
+        let mut indexed = 0;
+
+        for (rowid, json) in rows {
+            match json.parse::<geojson::Feature>() {
+                Ok(feature) => {
+                    Self::index_feature(&mut transaction, rowid, &feature).await.unwrap();
+                    indexed += 1;
+                }
+                Err(e) => tracing::error!("Row {} is not a valid feature, not indexed: {}", rowid, e),
+            }
+        }
+
+        transaction.commit().await.unwrap();
+        tracing::info!("{} features indexed by bbox", indexed);
+    }
+
+    /// Store the feature's envelope for the row, features without geometry are not indexed
+    async fn index_feature(
+        connection: &mut sqlx::SqliteConnection,
+        rowid: i64,
+        feature: &geojson::Feature,
+    ) -> Result<(), sqlx::Error> {
+        let Some(envelope) = feature
+            .geometry
+            .as_ref()
+            .and_then(|geometry| mvt::envelope(&geometry.value))
+        else {
+            return Ok(());
+        };
+
+        sqlx::query(
+            "INSERT OR REPLACE INTO lines_bbox (id, min_lon, max_lon, min_lat, max_lat) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(rowid)
+        .bind(envelope[0])
+        .bind(envelope[2])
+        .bind(envelope[1])
+        .bind(envelope[3])
+        .execute(connection)
+        .await?;
+        Ok(())
+    }
+
+    /// Move features stored before channels to the default channel
+    async fn add_channel_column(pool: &SqlitePool) {
+        let exists: bool =
+            sqlx::query_scalar("SELECT COUNT(*) > 0 FROM pragma_table_info('lines') WHERE name = 'channel';")
+                .fetch_one(pool)
+                .await
+                .unwrap();
+
+        if exists {
+            return;
+        }
+
+        // ALTER TABLE does not accept bound parameters
+        let qry = format!(
+            "ALTER TABLE lines ADD COLUMN channel TEXT NOT NULL DEFAULT '{}';",
+            DEFAULT_CHANNEL
+        );
+
+        sqlx::query(&qry).execute(pool).await.unwrap();
+        tracing::info!("Stored features are moved to the channel '{}'", DEFAULT_CHANNEL);
+    }
+
+    async fn schema_version(pool: &SqlitePool) -> i64 {
+        sqlx::query_scalar("PRAGMA user_version;")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn set_schema_version(pool: &SqlitePool, version: i64) {
+        // PRAGMA does not accept bound parameters
+        let qry = format!("PRAGMA user_version = {};", version);
+
+        sqlx::query(&qry).execute(pool).await.unwrap();
+    }
+
+    /// Warn when the database still keeps positions in legacy lat/lon order, empty database is marked as converted
+    async fn check_axis_order(pool: &SqlitePool) {
+        if Self::schema_version(pool).await >= SCHEMA_VERSION_LON_LAT {
+            return;
+        }
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM lines;")
+            .fetch_one(pool)
+            .await
+            .unwrap();
+
+        if count == 0 {
+            Self::set_schema_version(pool, SCHEMA_VERSION_LON_LAT).await;
+        } else {
+            tracing::warn!(
+                "Database keeps {} features in legacy lat/lon axis order, writes are refused until `waist {}` converts them",
+                count,
+                COMMAND_MIGRATE_AXIS_ORDER
+            );
+        }
+    }
+
+    /// One-off conversion of stored features from lat/lon to lon/lat axis order
+    async fn migrate_axis_order(&self) {
+        if Self::schema_version(&self.sqlite).await >= SCHEMA_VERSION_LON_LAT {
+            tracing::info!("Database already uses lon/lat axis order, nothing to do");
+            return;
+        }
+
+        let mut transaction = self.sqlite.begin().await.unwrap();
+        let rows: Vec<(i64, String)> = sqlx::query_as("SELECT rowid, json FROM lines;")
+            .fetch_all(&mut *transaction)
+            .await
+            .unwrap();
+        let mut converted = 0;
+
+        for (rowid, json) in rows {
+            match json.parse::<geojson::Feature>() {
+                Ok(mut feature) => {
+                    swap_feature_axes(&mut feature);
+                    sqlx::query("UPDATE lines SET json = $1 WHERE rowid = $2")
+                        .bind(feature.to_string())
+                        .bind(rowid)
+                        .execute(&mut *transaction)
+                        .await
+                        .unwrap();
+                    Self::index_feature(&mut transaction, rowid, &feature).await.unwrap();
+                    converted += 1;
+                }
+                Err(e) => tracing::error!("Row {} is not a valid feature, skipped: {}", rowid, e),
+            }
+        }
+
+        transaction.commit().await.unwrap();
+        Self::set_schema_version(&self.sqlite, SCHEMA_VERSION_LON_LAT).await;
+        tracing::info!("{} features converted to lon/lat axis order", converted);
+    }
+
+    /// Insert the row of the feature with stable id `fid`, new features get id of their row,
+    /// returns the row's id and the feature's stable id
+    async fn insert_row(
+        connection: &mut sqlx::SqliteConnection,
+        channel: &str,
+        fid: Option<i64>,
+        feature: &geojson::Feature,
+        deleted: bool,
+    ) -> Result<(i64, i64), sqlx::Error> {
+        let rowid = sqlx::query(
+            "INSERT INTO lines (timestamp, channel, fid, deleted, json) VALUES (datetime('now'), $1, $2, $3, $4)",
+        )
+        .bind(channel)
+        .bind(fid)
+        .bind(deleted)
+        .bind(feature.to_string())
+        .execute(&mut *connection)
+        .await?
+        .last_insert_rowid();
+
+        let fid = match fid {
+            Some(fid) => fid,
+            None => {
+                sqlx::query("UPDATE lines SET fid = rowid WHERE rowid = $1")
+                    .bind(rowid)
+                    .execute(&mut *connection)
+                    .await?;
+                rowid
+            }
+        };
+
+        if !deleted {
+            Self::index_feature(connection, rowid, feature).await?;
+        }
+        Ok((rowid, fid))
+    }
+
+    /// Remove the row with its envelope
+    async fn delete_row(connection: &mut sqlx::SqliteConnection, rowid: i64) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM lines_bbox WHERE id = $1")
+            .bind(rowid)
+            .execute(&mut *connection)
+            .await?;
+        sqlx::query("DELETE FROM lines WHERE rowid = $1")
+            .bind(rowid)
+            .execute(&mut *connection)
+            .await?;
+        Ok(())
+    }
+
+    /// Insert the feature as a new one, the feature's own id is ignored: features are replaced only by
+    /// `PUT /feature/:id`. The stored feature gets its stable id as the id, returns the stable id with the stored feature
+    async fn store_feature(
+        &self,
+        channel: &str,
+        mut feature: geojson::Feature,
+    ) -> Result<(i64, geojson::Feature), sqlx::Error> {
+        let mut transaction = self.sqlite.begin().await?;
+        let (rowid, fid) = Self::insert_row(&mut transaction, channel, None, &feature, false).await?;
+        let id = Some(geojson::feature::Id::Number(fid.into()));
+
+        if feature.id != id {
+            feature.id = id;
+            sqlx::query("UPDATE lines SET json = $1 WHERE rowid = $2")
+                .bind(feature.to_string())
+                .bind(rowid)
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        transaction.commit().await?;
+        Ok((fid, feature))
+    }
+
+    /// Current row of the feature with stable id `fid`, it may be a tombstone
+    async fn feature_row(&self, fid: i64) -> Result<Option<FeatureRow>, sqlx::Error> {
+        sqlx::query_as("SELECT rowid, channel, deleted, json FROM lines WHERE fid = $1 ORDER BY rowid DESC LIMIT 1")
+            .bind(fid)
+            .fetch_optional(&self.sqlite)
+            .await
+    }
+
+    /// Replace the row of the feature, geometry and properties of `feature` are stored with the feature's id.
+    /// Deleted feature is replaced by its tombstone, a tombstone replaced by a feature restores it.
+    /// Returns the channel and the stored feature
+    async fn replace_feature(
+        &self,
+        fid: i64,
+        feature: Option<geojson::Feature>,
+    ) -> Result<Option<(String, geojson::Feature)>, sqlx::Error> {
+        let mut transaction = self.sqlite.begin().await?;
+        let row: Option<FeatureRow> = sqlx::query_as("SELECT rowid, channel, deleted, json FROM lines WHERE fid = $1")
+            .bind(fid)
+            .fetch_optional(&mut *transaction)
+            .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let Ok(stored) = geojson::Feature::from_json_value(row.json) else {
+            return Ok(None);
+        };
+        let deleted = feature.is_none();
+        let feature = geojson::Feature {
+            id: stored.id,
+            ..feature.unwrap_or(geojson::Feature {
+                bbox: None,
+                geometry: None,
+                id: None,
+                properties: None,
+                foreign_members: None,
+            })
+        };
+
+        /* the new row is inserted first to get a greater id than the replaced one */
+        Self::insert_row(&mut transaction, &row.channel, Some(fid), &feature, deleted).await?;
+        Self::delete_row(&mut transaction, row.rowid).await?;
+        transaction.commit().await?;
+        Ok(Some((row.channel, feature)))
+    }
+
+    /// Number of the channel's features in the area and the greatest row id, they change with the features
+    async fn channel_version(&self, channel: &str, area: Option<&Area>) -> Result<(i64, i64), sqlx::Error> {
+        let qry = format!(
+            "SELECT count(*), coalesce(max(rowid), 0) FROM lines WHERE {};",
+            Area::filter(area)
+        );
+        let query = sqlx::query_as(&qry)
+            .bind(channel)
+            .bind(self.retention.of(channel).modifier());
+
+        Area::bind(query, area).fetch_one(&self.sqlite).await
+    }
+
+    /// Features of the channel intersecting the area, all features when the area is not set
+    async fn channel_features(&self, channel: &str, area: Option<&Area>) -> Result<Vec<geojson::Feature>, sqlx::Error> {
+        let qry = format!("SELECT json FROM lines WHERE deleted = 0 AND {};", Area::filter(area));
+        let query = sqlx::query_as(&qry)
+            .bind(channel)
+            .bind(self.retention.of(channel).modifier());
+        let result: Vec<QueryResult> = Area::bind(query, area).fetch_all(&self.sqlite).await?;
+
+        Ok(result
+            .into_iter()
+            .map(|feature_result| geojson::Feature::from_json_value(feature_result.json).unwrap())
+            .collect())
+    }
+
+    /// Changes of the channel in rows after `since`: stored features, stable ids of deleted features
+    /// and the number of the channel's features
+    async fn channel_changes(
+        &self,
+        channel: &str,
+        since: i64,
+    ) -> Result<(Vec<geojson::Feature>, Vec<i64>, i64), sqlx::Error> {
+        let filter = Area::filter(None);
+        let modifier = self.retention.of(channel).modifier();
+
+        let qry = format!(
+            "SELECT json FROM lines WHERE deleted = 0 AND rowid > $3 AND {};",
+            filter
+        );
+        let stored: Vec<QueryResult> = sqlx::query_as(&qry)
+            .bind(channel)
+            .bind(&modifier)
+            .bind(since)
+            .fetch_all(&self.sqlite)
+            .await?;
+
+        let qry = format!("SELECT fid FROM lines WHERE deleted = 1 AND rowid > $3 AND {};", filter);
+        let deleted: Vec<Option<i64>> = sqlx::query_scalar(&qry)
+            .bind(channel)
+            .bind(&modifier)
+            .bind(since)
+            .fetch_all(&self.sqlite)
+            .await?;
+
+        let qry = format!("SELECT count(*) FROM lines WHERE deleted = 0 AND {};", filter);
+        let count: i64 = sqlx::query_scalar(&qry)
+            .bind(channel)
+            .bind(&modifier)
+            .fetch_one(&self.sqlite)
+            .await?;
+
+        Ok((
+            stored
+                .into_iter()
+                .map(|feature_result| geojson::Feature::from_json_value(feature_result.json).unwrap())
+                .collect(),
+            deleted.into_iter().flatten().collect(),
+            count,
+        ))
+    }
+
+    /// Random channel id, channels are created by the first feature posted to them
+    async fn mint_channel(&self) -> Result<String, sqlx::Error> {
+        sqlx::query_scalar("SELECT lower(hex(randomblob(8)))")
+            .fetch_one(&self.sqlite)
+            .await
+    }
+
+    /// Check access of the token, or of anonymous client when it is not set, to the channel
+    async fn authorize(&self, token: Option<&str>, channel: &str, required: auth::Access) -> Result<(), auth::Denied> {
+        auth::authorize(&self.sqlite, &self.auth, token, channel, required).await
+    }
+
+    /// Send the update to subscribers of its channel
+    fn publish(&self, update: ChannelUpdate) {
+        let mut updates = self.updates.lock().unwrap();
+
+        if let Some(sender) = updates.get(&update.channel) {
+            let channel = update.channel.clone();
+
+            if sender.send(update).is_err() {
+                /* all subscribers left */
+                updates.remove(&channel);
+            }
+        }
+    }
+
+    /// Receiver of updates of the channel
+    fn subscribe(&self, channel: &str) -> broadcast::Receiver<ChannelUpdate> {
+        let mut updates = self.updates.lock().unwrap();
+
+        updates.retain(|_, sender| sender.receiver_count() > 0);
+        updates
+            .entry(channel.to_string())
+            .or_insert_with(|| broadcast::channel(UPDATES_CAPACITY).0)
+            .subscribe()
+    }
+
+    async fn new(db_url: &String, retention: retention::RetentionConfig, auth: auth::AuthConfig) -> Self {
+        let db_url = format!("sqlite://{}", db_url);
         let sqlite = Self::create_db(&db_url).await;
+        let legacy_axis_order = Self::schema_version(&sqlite).await < SCHEMA_VERSION_LON_LAT;
 
-        Self { json: None, sqlite }
+        Self {
+            json: None,
+            sqlite,
+            updates: Default::default(),
+            retention,
+            auth,
+            legacy_axis_order,
+        }
     }
 }
 
+/// Answer to writes while stored features are in legacy lat/lon order
+fn legacy_axis_order_response() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
+        format!(
+            "Stored features are in legacy lat/lon axis order, run `waist {}` to convert them",
+            COMMAND_MIGRATE_AXIS_ORDER
+        ),
+    )
+        .into_response()
+}
+
 impl Drop for ServerState {
     fn drop(&mut self) {
         tokio::task::block_in_place(move || {
@@ -78,40 +666,130 @@ async fn options_handler_new() -> impl IntoResponse {
             (header::ACCESS_CONTROL_ALLOW_METHODS, "POST, OPTIONS"),
             (
                 header::ACCESS_CONTROL_ALLOW_HEADERS,
-                "Origin, X-Requested-With, Content-Type",
+                "Origin, X-Requested-With, Content-Type, Accept, Authorization",
             ),
         ],
         "",
     )
 }
 
+/// Channel ids are path segments chosen by clients or minted by `POST /channel`
+fn is_valid_channel(id: &str) -> bool {
+    !id.is_empty() && !id.contains(|x: char| x.is_whitespace() || x.is_control())
+}
+
+/// Store features in the channel of the path, `POST /new` stores them in the default channel.
+/// Answers with the channel id, or with the channel and stable ids of the posted features when JSON is accepted,
+/// ids of features which are not stored are `null`
 #[debug_handler]
 async fn post_handler_new(
     extract::State(state): extract::State<SharedServerState>,
+    channel: Option<extract::Path<String>>,
+    headers: HeaderMap,
     extract::Json(payload): extract::Json<GeoJson>,
-) -> impl IntoResponse {
+) -> Response {
+    let channel = channel.map_or(DEFAULT_CHANNEL.to_string(), |extract::Path(channel)| channel);
+
+    if !is_valid_channel(&channel) {
+        return (StatusCode::BAD_REQUEST, [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")]).into_response();
+    }
+
+    if let Err(denied) = state
+        .read()
+        .await
+        .authorize(auth::bearer(&headers), &channel, auth::Access::Write)
+        .await
+    {
+        return denied.into_response();
+    }
+
+    if state.read().await.legacy_axis_order {
+        return legacy_axis_order_response();
+    }
+
     state.write().await.json = Some(payload.clone());
 
-    let pool = &state.write().await.sqlite;
+    let state = state.read().await;
+
+    let mut ids = Vec::new();
 
     match &payload {
         GeoJson::Geometry(_) => {}
         GeoJson::Feature(_) => {}
         GeoJson::FeatureCollection(fc) => {
+            let mut inserted = Vec::new();
+
             for feature in &fc.features {
-                let result = sqlx::query("INSERT INTO lines (timestamp, json) VALUES (datetime('now'), $1)")
-                    .bind(feature.to_string())
-                    .execute(pool)
-                    .await;
-                match result {
-                    Ok(_) => {}
-                    Err(e) => tracing::error!("DB insert fail: {:?}", e),
+                match state.store_feature(&channel, feature.clone()).await {
+                    Ok((fid, feature)) => {
+                        ids.push(geojson::JsonValue::from(fid));
+                        inserted.push(feature);
+                    }
+                    Err(e) => {
+                        tracing::error!("DB insert fail: {:?}", e);
+                        /* ids stay at positions of their features */
+                        ids.push(geojson::JsonValue::Null);
+                    }
                 }
             }
+
+            if !inserted.is_empty() {
+                state.publish(ChannelUpdate::features(&channel, inserted));
+            }
         }
     }
 
-    ([(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")], "world")
+    /* old clients expect the bare channel id */
+    let wants_json = headers
+        .get(header::ACCEPT)
+        .and_then(|x| x.to_str().ok())
+        .is_some_and(|x| x.contains("application/json"));
+
+    if wants_json {
+        let body = geojson::JsonValue::from_iter([
+            ("channel".to_string(), geojson::JsonValue::from(channel)),
+            ("ids".to_string(), geojson::JsonValue::from(ids)),
+        ]);
+
+        (
+            [
+                (header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"),
+                (header::CONTENT_TYPE, "application/json"),
+            ],
+            body.to_string(),
+        )
+            .into_response()
+    } else {
+        ([(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")], channel).into_response()
+    }
+}
+
+/// Issue a fresh channel id, only clients allowed to write to any channel may do it
+async fn post_handler_channel(
+    extract::State(state): extract::State<SharedServerState>,
+    headers: HeaderMap,
+) -> Response {
+    let state = state.read().await;
+
+    if let Err(denied) = state
+        .authorize(auth::bearer(&headers), auth::ANY_CHANNEL, auth::Access::Write)
+        .await
+    {
+        return denied.into_response();
+    }
+
+    match state.mint_channel().await {
+        Ok(id) => (StatusCode::OK, [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")], id).into_response(),
+        Err(e) => {
+            tracing::error!("Channel id not minted: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
+                String::new(),
+            )
+                .into_response()
+        }
+    }
 }
 
 #[derive(sqlx::FromRow)]
@@ -119,33 +797,390 @@ struct QueryResult {
     json: sqlx::types::JsonValue,
 }
 
-async fn handler_get(
-    extract::State(state): extract::State<SharedServerState>,
-    extract::Path(_id): extract::Path<String>,
-) -> impl IntoResponse {
-    let pool = &state.write().await.sqlite;
+/// Size of map tiles in pixels to compute the size of a pixel at the zoom
+const TILE_SIZE: f64 = 256.0;
 
-    let result: Vec<QueryResult> =
-        sqlx::query_as("SELECT json FROM lines WHERE timestamp > datetime('now', '-7 day');")
-            .fetch_all(pool)
-            .await
-            .unwrap();
+/// Part of the channel requested by the client
+struct Area {
+    /// `[west, south, east, north]`, west is greater than east when the bbox crosses the antimeridian
+    bbox: [f64; 4],
+    /// Features smaller than this in degrees are skipped, points are always kept
+    min_size: f64,
+}
+
+type SqliteQueryAs<'q, O> = sqlx::query::QueryAs<'q, sqlx::Sqlite, O, sqlx::sqlite::SqliteArguments<'q>>;
+
+impl Area {
+    fn new(bbox: [f64; 4], zoom: Option<u8>) -> Self {
+        let min_size = zoom.map_or(0.0, |zoom| 360.0 / (TILE_SIZE * 2f64.powi(zoom as i32)));
+
+        Self { bbox, min_size }
+    }
+
+    /// "minlon,minlat,maxlon,maxlat", minlon is greater than maxlon when the bbox crosses the antimeridian
+    fn parse_bbox(bbox: &str) -> Option<[f64; 4]> {
+        let values: Vec<f64> = bbox
+            .split(',')
+            .map(|x| x.trim().parse::<f64>().ok().filter(|x| x.is_finite()))
+            .collect::<Option<_>>()?;
+        let bbox: [f64; 4] = values.try_into().ok()?;
+        let crosses_antimeridian = bbox[0] > bbox[2] && bbox[0] <= 180.0 && bbox[2] >= -180.0;
+
+        if (bbox[0] <= bbox[2] || crosses_antimeridian) && bbox[1] <= bbox[3] {
+            Some(bbox)
+        } else {
+            None
+        }
+    }
+
+    /// Longitude ranges of the bbox, the bbox crossing the antimeridian is split in two,
+    /// otherwise both ranges are the same
+    fn lon_ranges(&self) -> [[f64; 2]; 2] {
+        let [west, _, east, _] = self.bbox;
+
+        if west <= east {
+            [[west, east], [west, east]]
+        } else {
+            [[west, 180.0], [-180.0, east]]
+        }
+    }
+
+    /// Condition of the rows of the channel `$1` not expired by retention modifier `$2`,
+    /// the area's values `$3`..`$9` are bound by `bind`
+    fn filter(area: Option<&Area>) -> &'static str {
+        match area {
+            Some(_) => {
+                "channel = $1 AND ($2 IS NULL OR timestamp > datetime('now', $2)) AND rowid IN (SELECT id FROM lines_bbox \
+                 WHERE ((min_lon <= $5 AND max_lon >= $3) OR (min_lon <= $9 AND max_lon >= $8)) \
+                 AND min_lat <= $6 AND max_lat >= $4 \
+                 AND (max_lon - min_lon >= $7 OR max_lat - min_lat >= $7 OR (max_lon = min_lon AND max_lat = min_lat)))"
+            }
+            None => "channel = $1 AND ($2 IS NULL OR timestamp > datetime('now', $2))",
+        }
+    }
+
+    fn bind<'q, O>(query: SqliteQueryAs<'q, O>, area: Option<&Area>) -> SqliteQueryAs<'q, O> {
+        match area {
+            Some(area) => {
+                let [[west, east], [second_west, second_east]] = area.lon_ranges();
 
+                query
+                    .bind(west)
+                    .bind(area.bbox[1])
+                    .bind(east)
+                    .bind(area.bbox[3])
+                    .bind(area.min_size)
+                    .bind(second_west)
+                    .bind(second_east)
+            }
+            None => query,
+        }
+    }
+}
+
+/// Query of `/get/:id`
+#[derive(serde::Deserialize)]
+struct GetQuery {
+    /// "minlon,minlat,maxlon,maxlat"
+    bbox: Option<String>,
+    /// Features smaller than a pixel at the zoom are skipped
+    zoom: Option<u8>,
+    /// Cursor of the client's copy of the whole channel, only later changes are answered
+    since: Option<i64>,
+}
+
+async fn options_handler_get() -> impl IntoResponse {
     (
         [
-            (header::CONTENT_TYPE, "application/geo+json"),
             (header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"),
+            (header::ACCESS_CONTROL_ALLOW_METHODS, "GET, OPTIONS"),
+            (header::ACCESS_CONTROL_ALLOW_HEADERS, "If-None-Match, Authorization"),
         ],
+        "",
+    )
+}
+
+/// Features of the channel optionally limited by bbox, unchanged features are answered with 304
+/// when the client sends their ETag. The whole channel is answered with its cursor,
+/// requested with the cursor it is answered with changes made after the cursor
+async fn handler_get(
+    extract::State(state): extract::State<SharedServerState>,
+    extract::Path(id): extract::Path<String>,
+    extract::Query(query): extract::Query<GetQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let area = match query.bbox.as_deref().map(Area::parse_bbox) {
+        /* cursors are given for the whole channel only */
+        Some(Some(_)) if query.since.is_some() => {
+            return (StatusCode::BAD_REQUEST, [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")]).into_response();
+        }
+        Some(Some(bbox)) => Some(Area::new(bbox, query.zoom)),
+        Some(None) => {
+            return (StatusCode::BAD_REQUEST, [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")]).into_response();
+        }
+        None => None,
+    };
+    let state = state.read().await;
+
+    if let Err(denied) = state.authorize(auth::bearer(&headers), &id, auth::Access::Read).await {
+        return denied.into_response();
+    }
+
+    /* replaced features get new rows and expired ones leave the window, so both values change */
+    let (count, max_rowid) = state.channel_version(&id, area.as_ref()).await.unwrap();
+    let etag = format!("\"{}-{}\"", max_rowid, count);
+    let cache_headers = [
+        (header::ACCESS_CONTROL_ALLOW_ORIGIN, "*".to_string()),
+        (header::ACCESS_CONTROL_EXPOSE_HEADERS, "ETag".to_string()),
+        (header::CACHE_CONTROL, "no-cache".to_string()),
+        (header::ETAG, etag.clone()),
+    ];
+
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|x| x.to_str().ok())
+        .is_some_and(|x| x.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+
+    /* clients keeping purged features would not learn they are gone from the changes */
+    let since = match query.since {
+        Some(since) if retention::purged_since(&state.sqlite, &id, since).await.unwrap() => None,
+        since => since,
+    };
+    let mut members = geojson::JsonObject::new();
+    let features = match since {
+        Some(since) => {
+            let (features, deleted, count) = state.channel_changes(&id, since).await.unwrap();
+
+            members.insert(MEMBER_DELETED.to_string(), geojson::JsonValue::from(deleted));
+            members.insert(MEMBER_COUNT.to_string(), geojson::JsonValue::from(count));
+            features
+        }
+        None => state.channel_features(&id, area.as_ref()).await.unwrap(),
+    };
+
+    if area.is_none() {
+        members.insert(MEMBER_CURSOR.to_string(), geojson::JsonValue::from(max_rowid));
+    }
+
+    (
+        cache_headers,
+        [(header::CONTENT_TYPE, "application/geo+json")],
         geojson::FeatureCollection {
             bbox: None,
-            features: result
-                .iter()
-                .map(|feature_result| geojson::Feature::from_json_value(feature_result.json.clone()).unwrap())
-                .collect(),
-            foreign_members: None,
+            features,
+            foreign_members: Some(members).filter(|x| !x.is_empty()),
         }
         .to_string(),
     )
+        .into_response()
+}
+
+/// Mapbox Vector Tile of the channel, `y` may have the ".mvt" extension
+async fn handler_tile(
+    extract::State(state): extract::State<SharedServerState>,
+    extract::Path((id, z, x, y)): extract::Path<(String, u8, u32, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(tile) = y
+        .trim_end_matches(".mvt")
+        .parse::<u32>()
+        .ok()
+        .and_then(|y| mvt::TileId::new(z, x, y).ok())
+    else {
+        return (StatusCode::BAD_REQUEST, [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")]).into_response();
+    };
+
+    let state = state.read().await;
+
+    if let Err(denied) = state.authorize(auth::bearer(&headers), &id, auth::Access::Read).await {
+        return denied.into_response();
+    }
+
+    let area = Area::new(tile.bounds(), Some(tile.z));
+    let features = state.channel_features(&id, Some(&area)).await.unwrap();
+
+    (
+        [
+            (header::CONTENT_TYPE, "application/vnd.mapbox-vector-tile"),
+            (header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"),
+        ],
+        mvt::encode(&tile, &id, &features),
+    )
+        .into_response()
+}
+
+async fn options_handler_feature() -> impl IntoResponse {
+    (
+        [
+            (header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"),
+            (header::ACCESS_CONTROL_ALLOW_METHODS, "GET, PUT, DELETE, OPTIONS"),
+            (
+                header::ACCESS_CONTROL_ALLOW_HEADERS,
+                "Origin, X-Requested-With, Content-Type, Authorization",
+            ),
+        ],
+        "",
+    )
+}
+
+/// Current row of the feature when the client has `required` access to its channel
+async fn authorized_feature_row(
+    state: &ServerState,
+    headers: &HeaderMap,
+    fid: i64,
+    required: auth::Access,
+) -> Result<Option<FeatureRow>, Response> {
+    match state.feature_row(fid).await {
+        Ok(Some(row)) => match state.authorize(auth::bearer(headers), &row.channel, required).await {
+            Ok(_) => Ok(Some(row)),
+            Err(denied) => Err(denied.into_response()),
+        },
+        Ok(None) => Ok(None),
+        Err(e) => {
+            tracing::error!("Feature {} not read: {:?}", fid, e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
+            )
+                .into_response())
+        }
+    }
+}
+
+/// Feature by its stable id, deleted feature is answered with 410
+async fn handler_feature_get(
+    extract::State(state): extract::State<SharedServerState>,
+    extract::Path(fid): extract::Path<i64>,
+    headers: HeaderMap,
+) -> Response {
+    match authorized_feature_row(&*state.read().await, &headers, fid, auth::Access::Read).await {
+        Ok(Some(row)) if row.deleted => {
+            (StatusCode::GONE, [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")]).into_response()
+        }
+        Ok(Some(row)) => (
+            [
+                (header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"),
+                (header::CONTENT_TYPE, "application/geo+json"),
+            ],
+            row.json.to_string(),
+        )
+            .into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")]).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Replace geometry and properties of the feature, or delete it leaving a tombstone when `feature` is not set
+async fn change_feature(
+    state: SharedServerState,
+    headers: HeaderMap,
+    fid: i64,
+    feature: Option<geojson::Feature>,
+) -> Response {
+    let deleted = feature.is_none();
+    let state = state.read().await;
+
+    if state.legacy_axis_order {
+        return legacy_axis_order_response();
+    }
+
+    if let Err(response) = authorized_feature_row(&state, &headers, fid, auth::Access::Write).await {
+        return response;
+    }
+
+    match state.replace_feature(fid, feature).await {
+        Ok(Some((channel, feature))) => {
+            let update = if deleted {
+                ChannelUpdate::deleted(&channel, feature.id.clone())
+            } else {
+                ChannelUpdate::features(&channel, vec![feature.clone()])
+            };
+
+            state.publish(update);
+
+            if deleted {
+                (StatusCode::NO_CONTENT, [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")]).into_response()
+            } else {
+                (
+                    [
+                        (header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"),
+                        (header::CONTENT_TYPE, "application/geo+json"),
+                    ],
+                    feature.to_string(),
+                )
+                    .into_response()
+            }
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")]).into_response(),
+        Err(e) => {
+            tracing::error!("Feature {} not changed: {:?}", fid, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Replace geometry and properties of the feature, its id and channel are kept, deleted feature is restored
+async fn handler_feature_put(
+    extract::State(state): extract::State<SharedServerState>,
+    extract::Path(fid): extract::Path<i64>,
+    headers: HeaderMap,
+    extract::Json(feature): extract::Json<geojson::Feature>,
+) -> Response {
+    change_feature(state, headers, fid, Some(feature)).await
+}
+
+/// Delete the feature, its tombstone is kept to tell subscribers about it
+async fn handler_feature_delete(
+    extract::State(state): extract::State<SharedServerState>,
+    extract::Path(fid): extract::Path<i64>,
+    headers: HeaderMap,
+) -> Response {
+    change_feature(state, headers, fid, None).await
+}
+
+/// Query of `/subscribe/:id`
+#[derive(serde::Deserialize)]
+struct SubscribeQuery {
+    /// Token of clients which can not set headers, browsers' EventSource
+    access_token: Option<String>,
+}
+
+/// Server-Sent Events stream of features added to the channel `id`
+async fn handler_subscribe(
+    extract::State(state): extract::State<SharedServerState>,
+    extract::Path(id): extract::Path<String>,
+    extract::Query(query): extract::Query<SubscribeQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let state = state.read().await;
+    let token = auth::bearer(&headers).or(query.access_token.as_deref());
+
+    if let Err(denied) = state.authorize(token, &id, auth::Access::Read).await {
+        return denied.into_response();
+    }
+
+    let updates = BroadcastStream::new(state.subscribe(&id));
+    let events = updates.map(move |update| match update {
+        Ok(update) => Ok::<_, Infallible>(Event::default().event(update.event).data(update.data)),
+        Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+            tracing::warn!("Subscriber of '{}' lagged behind by {} updates", id, skipped);
+            Ok(Event::default().event(EVENT_RESYNC).data(""))
+        }
+    });
+
+    (
+        [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
+        Sse::new(events).keep_alive(KeepAlive::default()),
+    )
+        .into_response()
 }
 
 #[derive(Derivative, serde::Deserialize, serde::Serialize, Debug)]
@@ -171,6 +1206,12 @@ struct Config {
     #[derivative(Default(value = r#"3000"#))]
     port: u16,
     tls_acme: TslAcme,
+    /// Absent in configs written before retention
+    #[serde(default)]
+    retention: retention::RetentionConfig,
+    /// Absent in configs written before tokens, anyone may read then and writes need tokens
+    #[serde(default)]
+    auth: auth::AuthConfig,
 }
 
 fn read_config() -> Config {
@@ -224,25 +1265,75 @@ fn build_acme_acceptor(config: &Config) -> rustls_acme::axum::AxumAcceptor {
 async fn main() {
     tracing_subscriber::fmt().with_target(false).compact().init();
     let config: Config = read_config();
-    let shared_server_state = Arc::new(RwLock::new(ServerState::new(&config.sqlite).await));
+    let shared_server_state = Arc::new(RwLock::new(
+        ServerState::new(&config.sqlite, config.retention.clone(), config.auth.clone()).await,
+    ));
+
+    match std::env::args().nth(1).as_deref() {
+        Some(COMMAND_MIGRATE_AXIS_ORDER) => {
+            shared_server_state.read().await.migrate_axis_order().await;
+            return;
+        }
+        Some(command) => {
+            tracing::error!(
+                "Unknown command '{}', supported: {}",
+                command,
+                COMMAND_MIGRATE_AXIS_ORDER
+            );
+            return;
+        }
+        None => {}
+    }
+
+    retention::spawn_purge_job(
+        shared_server_state.read().await.sqlite.clone(),
+        config.retention.clone(),
+    );
+
+    let new_service = post_handler_new
+        .layer((
+            DefaultBodyLimit::disable(),
+            RequestBodyLimitLayer::new(1024 * 1_000 /* ~1mb */),
+        ))
+        .with_state(Arc::clone(&shared_server_state));
 
     let app = Router::new()
         .route("/", get(|| async { "What are you doing here?" }))
+        .route("/new", options(options_handler_new).post_service(new_service.clone()))
+        .route("/new/:channel", options(options_handler_new).post_service(new_service))
+        .route("/channel", post(post_handler_channel).options(options_handler_new))
         .route(
-            "/new",
-            options(options_handler_new).post_service(
-                post_handler_new
-                    .layer((
-                        DefaultBodyLimit::disable(),
-                        RequestBodyLimitLayer::new(1024 * 1_000 /* ~1mb */),
-                    ))
-                    .with_state(Arc::clone(&shared_server_state)),
-            ),
+            "/get/:id",
+            get(handler_get)
+                .options(options_handler_get)
+                .layer(CompressionLayer::new()),
+        )
+        .route(
+            "/feature/:id",
+            get(handler_feature_get)
+                .put(handler_feature_put)
+                .delete(handler_feature_delete)
+                .options(options_handler_feature)
+                .layer(RequestBodyLimitLayer::new(1024 * 1_000 /* ~1mb */)),
+        )
+        .route("/subscribe/:id", get(handler_subscribe))
+        .route(
+            "/tile/:id/:z/:x/:y",
+            get(handler_tile)
+                .options(options_handler_get)
+                .layer(CompressionLayer::new()),
         )
-        .route("/get/:id", get(handler_get).layer(CompressionLayer::new()))
         .layer(
             trace::TraceLayer::new_for_http()
-                .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
+                /* browsers' subscriptions pass tokens in the query */
+                .make_span_with(|request: &extract::Request| {
+                    tracing::info_span!(
+                        "request",
+                        method = %request.method(),
+                        uri = %auth::redacted_uri(request.uri()),
+                        version = ?request.version(),
+                    )
+                })
                 .on_response(trace::DefaultOnResponse::new().level(Level::INFO)),
         )
         .with_state(Arc::clone(&shared_server_state));
@@ -261,3 +1352,88 @@ async fn main() {
         server.serve(svc).await.unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geojson::feature::Id;
+
+    /// Server state over an empty in-memory database
+    pub(crate) async fn test_state() -> ServerState {
+        ServerState {
+            json: None,
+            sqlite: ServerState::build_db_schema("sqlite::memory:").await,
+            updates: Default::default(),
+            retention: Default::default(),
+            auth: Default::default(),
+            legacy_axis_order: false,
+        }
+    }
+
+    pub(crate) fn point(id: Option<i64>) -> geojson::Feature {
+        geojson::Feature {
+            bbox: None,
+            geometry: Some(geojson::Geometry::new(geojson::Value::Point(vec![30.0, 60.0]))),
+            id: id.map(|x| Id::Number(x.into())),
+            properties: None,
+            foreign_members: None,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn posted_ids_do_not_replace_features() {
+        let state = test_state().await;
+        let (first, _) = state.store_feature("a", point(None)).await.unwrap();
+        let (second, stored) = state.store_feature("a", point(Some(first))).await.unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(stored.id, Some(Id::Number(second.into())));
+        assert!(!state.feature_row(first).await.unwrap().unwrap().deleted);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn posted_ids_do_not_restore_tombstones() {
+        let state = test_state().await;
+        let (fid, _) = state.store_feature("a", point(None)).await.unwrap();
+
+        state.replace_feature(fid, None).await.unwrap().unwrap();
+        state.store_feature("a", point(Some(fid))).await.unwrap();
+
+        assert!(state.feature_row(fid).await.unwrap().unwrap().deleted);
+    }
+
+    #[test]
+    fn feature_axes_are_swapped_everywhere() {
+        let collection = geojson::Geometry::new(geojson::Value::GeometryCollection(vec![
+            geojson::Geometry::new(geojson::Value::Point(vec![1.0, 2.0, 3.0])),
+            geojson::Geometry::new(geojson::Value::MultiPolygon(vec![vec![vec![
+                vec![4.0, 5.0],
+                vec![6.0, 7.0],
+            ]]])),
+        ]));
+        let mut feature = geojson::Feature {
+            bbox: Some(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]),
+            geometry: Some(collection),
+            id: None,
+            properties: None,
+            foreign_members: None,
+        };
+        let original = feature.clone();
+
+        swap_feature_axes(&mut feature);
+
+        assert_eq!(feature.bbox, Some(vec![2.0, 1.0, 3.0, 5.0, 4.0, 6.0]));
+        let Some(geojson::Value::GeometryCollection(geometries)) = feature.geometry.as_ref().map(|x| &x.value) else {
+            panic!("geometry collection is lost");
+        };
+        assert_eq!(geometries[0].value, geojson::Value::Point(vec![2.0, 1.0, 3.0]));
+        assert_eq!(
+            geometries[1].value,
+            geojson::Value::MultiPolygon(vec![vec![vec![vec![5.0, 4.0], vec![7.0, 6.0]]]])
+        );
+
+        swap_feature_axes(&mut feature);
+
+        assert_eq!(feature, original);
+    }
+}
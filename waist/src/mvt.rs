@@ -0,0 +1,580 @@
+//! Mapbox Vector Tile encoding of stored features
+//!
+//! Protobuf messages are written by hand, see
+//! https://github.com/mapbox/vector-tile-spec/blob/master/2.1/vector_tile.proto
+use geojson::{JsonValue, Value};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+/// Size of the tile in its coordinates
+pub const EXTENT: u32 = 4096;
+/// Geometries are kept this far outside of the tile to hide clipping edges
+const BUFFER: f64 = 256.0;
+/// Deepest zoom of served tiles, megingjord's `MAX_TILE_ZOOM` must be the same
+pub const MAX_ZOOM: u8 = 16;
+
+const GEOM_POINT: u64 = 1;
+const GEOM_LINESTRING: u64 = 2;
+const GEOM_POLYGON: u64 = 3;
+
+const CMD_MOVE_TO: u32 = 1;
+const CMD_LINE_TO: u32 = 2;
+const CMD_CLOSE_PATH: u32 = 7;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TileId {
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+#[derive(Debug)]
+pub struct TileIdError;
+
+impl TileId {
+    pub fn new(z: u8, x: u32, y: u32) -> Result<Self, TileIdError> {
+        if z > MAX_ZOOM || x >= 1 << z || y >= 1 << z {
+            return Err(TileIdError);
+        }
+        Ok(Self { z, x, y })
+    }
+
+    /// Position in the tile's coordinates, the tile is `[0, EXTENT]` on both axes
+    fn project(&self, position: &[f64]) -> (f64, f64) {
+        let scale = (1u64 << self.z) as f64;
+        let lat = position[1].clamp(-85.051_128, 85.051_128).to_radians();
+        let x = (position[0] + 180.0) / 360.0 * scale;
+        let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * scale;
+
+        ((x - self.x as f64) * EXTENT as f64, (y - self.y as f64) * EXTENT as f64)
+    }
+
+    /// Lon/lat envelope of the tile with its buffer as `[west, south, east, north]`
+    pub fn bounds(&self) -> [f64; 4] {
+        let scale = (1u64 << self.z) as f64;
+        let buffer = BUFFER / EXTENT as f64;
+        let lon = |x: f64| x / scale * 360.0 - 180.0;
+        let lat = |y: f64| (PI * (1.0 - 2.0 * y / scale)).sinh().atan().to_degrees();
+
+        [
+            lon(self.x as f64 - buffer),
+            lat(self.y as f64 + 1.0 + buffer),
+            lon(self.x as f64 + 1.0 + buffer),
+            lat(self.y as f64 - buffer),
+        ]
+    }
+}
+
+/// Envelope of the geometry as `[west, south, east, north]`
+pub fn envelope(value: &Value) -> Option<[f64; 4]> {
+    let union = |a: Option<[f64; 4]>, b: [f64; 4]| match a {
+        Some(a) => Some([a[0].min(b[0]), a[1].min(b[1]), a[2].max(b[2]), a[3].max(b[3])]),
+        None => Some(b),
+    };
+    let positions: Vec<&Vec<f64>> = match value {
+        Value::Point(point) => vec![point],
+        Value::MultiPoint(points) | Value::LineString(points) => points.iter().collect(),
+        Value::MultiLineString(lines) | Value::Polygon(lines) => lines.iter().flatten().collect(),
+        Value::MultiPolygon(polygons) => polygons.iter().flatten().flatten().collect(),
+        Value::GeometryCollection(geometries) => {
+            return geometries
+                .iter()
+                .filter_map(|geometry| envelope(&geometry.value))
+                .fold(None, union);
+        }
+    };
+
+    positions.iter().fold(None, |a, x| union(a, [x[0], x[1], x[0], x[1]]))
+}
+
+/// Protobuf message writer
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u32) {
+        self.varint(((field << 3) | wire_type) as u64);
+    }
+
+    fn uint(&mut self, field: u32, value: u64) {
+        self.key(field, 0);
+        self.varint(value);
+    }
+
+    fn double(&mut self, field: u32, value: f64) {
+        self.key(field, 1);
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, field: u32, bytes: &[u8]) {
+        self.key(field, 2);
+        self.varint(bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn packed(&mut self, field: u32, values: &[u32]) {
+        let mut packed = Message::default();
+
+        values.iter().for_each(|value| packed.varint(*value as u64));
+        self.bytes(field, &packed.0);
+    }
+}
+
+fn zigzag(value: i64) -> u32 {
+    ((value << 1) ^ (value >> 63)) as u32
+}
+
+/// Geometry commands of the feature
+#[derive(Default)]
+struct Commands {
+    commands: Vec<u32>,
+    cursor: (i64, i64),
+}
+
+impl Commands {
+    fn command(&mut self, id: u32, count: usize) {
+        self.commands.push(id | ((count as u32) << 3));
+    }
+
+    fn points(&mut self, points: &[(i64, i64)]) {
+        for point in points {
+            self.commands.push(zigzag(point.0 - self.cursor.0));
+            self.commands.push(zigzag(point.1 - self.cursor.1));
+            self.cursor = *point;
+        }
+    }
+
+    fn line(&mut self, line: &[(i64, i64)]) {
+        self.command(CMD_MOVE_TO, 1);
+        self.points(&line[..1]);
+        self.command(CMD_LINE_TO, line.len() - 1);
+        self.points(&line[1..]);
+    }
+
+    /// Ring without the closing point
+    fn ring(&mut self, ring: &[(i64, i64)]) {
+        self.line(ring);
+        self.command(CMD_CLOSE_PATH, 1);
+    }
+}
+
+/// Clipping rectangle in tile coordinates
+const CLIP_MIN: f64 = -BUFFER;
+const CLIP_MAX: f64 = EXTENT as f64 + BUFFER;
+
+fn inside(point: (f64, f64)) -> bool {
+    (CLIP_MIN..=CLIP_MAX).contains(&point.0) && (CLIP_MIN..=CLIP_MAX).contains(&point.1)
+}
+
+/// Parts of the line inside the clipping rectangle, Liang-Barsky per segment
+fn clip_line(line: &[(f64, f64)]) -> Vec<Vec<(f64, f64)>> {
+    let mut parts: Vec<Vec<(f64, f64)>> = Vec::new();
+    let mut current: Vec<(f64, f64)> = Vec::new();
+
+    for segment in line.windows(2) {
+        let (a, b) = (segment[0], segment[1]);
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let mut t0: f64 = 0.0;
+        let mut t1: f64 = 1.0;
+        let mut visible = true;
+
+        for (p, q) in [
+            (-dx, a.0 - CLIP_MIN),
+            (dx, CLIP_MAX - a.0),
+            (-dy, a.1 - CLIP_MIN),
+            (dy, CLIP_MAX - a.1),
+        ] {
+            if p == 0.0 {
+                visible &= q >= 0.0;
+            } else if p < 0.0 {
+                t0 = t0.max(q / p);
+            } else {
+                t1 = t1.min(q / p);
+            }
+        }
+
+        if !visible || t0 > t1 {
+            if current.len() > 1 {
+                parts.push(std::mem::take(&mut current));
+            }
+            current.clear();
+            continue;
+        }
+
+        let start = (a.0 + t0 * dx, a.1 + t0 * dy);
+        let end = (a.0 + t1 * dx, a.1 + t1 * dy);
+
+        if current.is_empty() {
+            current.push(start);
+        }
+        current.push(end);
+
+        /* the segment leaves the rectangle */
+        if t1 < 1.0 {
+            parts.push(std::mem::take(&mut current));
+        }
+    }
+
+    if current.len() > 1 {
+        parts.push(current);
+    }
+    parts
+}
+
+/// Ring clipped by the rectangle, Sutherland-Hodgman
+fn clip_ring(ring: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    let mut output = ring.to_vec();
+
+    for edge in 0..4 {
+        let input = std::mem::take(&mut output);
+        let is_inside = |p: (f64, f64)| match edge {
+            0 => p.0 >= CLIP_MIN,
+            1 => p.0 <= CLIP_MAX,
+            2 => p.1 >= CLIP_MIN,
+            _ => p.1 <= CLIP_MAX,
+        };
+        let intersection = |a: (f64, f64), b: (f64, f64)| {
+            let t = match edge {
+                0 => (CLIP_MIN - a.0) / (b.0 - a.0),
+                1 => (CLIP_MAX - a.0) / (b.0 - a.0),
+                2 => (CLIP_MIN - a.1) / (b.1 - a.1),
+                _ => (CLIP_MAX - a.1) / (b.1 - a.1),
+            };
+            (a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1))
+        };
+
+        for (i, current) in input.iter().enumerate() {
+            let previous = input[(i + input.len() - 1) % input.len()];
+
+            if is_inside(*current) {
+                if !is_inside(previous) {
+                    output.push(intersection(previous, *current));
+                }
+                output.push(*current);
+            } else if is_inside(previous) {
+                output.push(intersection(previous, *current));
+            }
+        }
+    }
+
+    output
+}
+
+/// Round to integer tile coordinates and drop repeated points
+fn round(points: &[(f64, f64)]) -> Vec<(i64, i64)> {
+    let mut rounded: Vec<(i64, i64)> = Vec::with_capacity(points.len());
+
+    for point in points {
+        let point = (point.0.round() as i64, point.1.round() as i64);
+
+        if rounded.last() != Some(&point) {
+            rounded.push(point);
+        }
+    }
+    rounded
+}
+
+/// Doubled signed area, positive for exterior rings of the tile
+fn ring_area(ring: &[(i64, i64)]) -> i64 {
+    (0..ring.len())
+        .map(|i| {
+            let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum()
+}
+
+/// Ring in tile coordinates without the closing point, `None` when it is clipped out
+fn tile_ring(tile: &TileId, ring: &[Vec<f64>], exterior: bool) -> Option<Vec<(i64, i64)>> {
+    let projected: Vec<(f64, f64)> = ring.iter().map(|x| tile.project(x)).collect();
+    let mut ring = round(&clip_ring(&projected));
+
+    if ring.len() > 1 && ring.first() == ring.last() {
+        ring.pop();
+    }
+
+    let area = ring_area(&ring);
+
+    if ring.len() < 3 || area == 0 {
+        return None;
+    }
+    if (area > 0) != exterior {
+        ring.reverse();
+    }
+    Some(ring)
+}
+
+/// Polygon without the exterior ring is clipped out with its holes
+fn polygon_commands(commands: &mut Commands, tile: &TileId, polygon: &[Vec<Vec<f64>>]) {
+    let Some(exterior) = polygon.first().and_then(|x| tile_ring(tile, x, true)) else {
+        return;
+    };
+
+    commands.ring(&exterior);
+    for hole in polygon[1..].iter().filter_map(|x| tile_ring(tile, x, false)) {
+        commands.ring(&hole);
+    }
+}
+
+/// Encoded geometries of the value as (type, commands), collections give several geometries
+fn geometries(tile: &TileId, value: &Value) -> Vec<(u64, Vec<u32>)> {
+    let mut commands = Commands::default();
+
+    let kind = match value {
+        Value::Point(point) => {
+            let point = tile.project(point);
+
+            if !inside(point) {
+                return Vec::new();
+            }
+            commands.command(CMD_MOVE_TO, 1);
+            commands.points(&round(&[point]));
+            GEOM_POINT
+        }
+        Value::MultiPoint(points) => {
+            let points: Vec<(f64, f64)> = points.iter().map(|x| tile.project(x)).filter(|x| inside(*x)).collect();
+
+            if points.is_empty() {
+                return Vec::new();
+            }
+            let points: Vec<(i64, i64)> = points
+                .iter()
+                .map(|x| (x.0.round() as i64, x.1.round() as i64))
+                .collect();
+
+            commands.command(CMD_MOVE_TO, points.len());
+            commands.points(&points);
+            GEOM_POINT
+        }
+        Value::LineString(line) => {
+            for part in clip_line(&line.iter().map(|x| tile.project(x)).collect::<Vec<_>>()) {
+                let part = round(&part);
+
+                if part.len() > 1 {
+                    commands.line(&part);
+                }
+            }
+            GEOM_LINESTRING
+        }
+        Value::MultiLineString(lines) => {
+            for line in lines {
+                for part in clip_line(&line.iter().map(|x| tile.project(x)).collect::<Vec<_>>()) {
+                    let part = round(&part);
+
+                    if part.len() > 1 {
+                        commands.line(&part);
+                    }
+                }
+            }
+            GEOM_LINESTRING
+        }
+        Value::Polygon(polygon) => {
+            polygon_commands(&mut commands, tile, polygon);
+            GEOM_POLYGON
+        }
+        Value::MultiPolygon(polygons) => {
+            for polygon in polygons {
+                polygon_commands(&mut commands, tile, polygon);
+            }
+            GEOM_POLYGON
+        }
+        Value::GeometryCollection(collection) => {
+            return collection
+                .iter()
+                .flat_map(|geometry| geometries(tile, &geometry.value))
+                .collect();
+        }
+    };
+
+    if commands.commands.is_empty() {
+        Vec::new()
+    } else {
+        vec![(kind, commands.commands)]
+    }
+}
+
+/// Items of the layer's table in the order of their indices
+#[derive(Default)]
+struct Table<T> {
+    items: Vec<T>,
+    indices: HashMap<T, u32>,
+}
+
+impl<T: Clone + Eq + std::hash::Hash> Table<T> {
+    fn index(&mut self, item: T) -> u32 {
+        if let Some(idx) = self.indices.get(&item) {
+            return *idx;
+        }
+
+        let idx = self.items.len() as u32;
+
+        self.indices.insert(item.clone(), idx);
+        self.items.push(item);
+        idx
+    }
+}
+
+/// Keys and values of the layer, shared by its features
+#[derive(Default)]
+struct Tags {
+    keys: Table<String>,
+    values: Table<Vec<u8>>,
+}
+
+impl Tags {
+    /// Encoded Value message, nested objects and arrays are stored as JSON strings
+    fn value(value: &JsonValue) -> Option<Vec<u8>> {
+        let mut message = Message::default();
+
+        match value {
+            JsonValue::Null => return None,
+            JsonValue::Bool(value) => message.uint(7, *value as u64),
+            JsonValue::Number(number) => match number.as_i64() {
+                Some(int) => message.uint(6, zigzag64(int)),
+                None => message.double(3, number.as_f64().unwrap_or_default()),
+            },
+            JsonValue::String(string) => message.bytes(1, string.as_bytes()),
+            other => message.bytes(1, other.to_string().as_bytes()),
+        }
+        Some(message.0)
+    }
+
+    fn tags(&mut self, properties: Option<&geojson::JsonObject>) -> Vec<u32> {
+        let mut tags = Vec::new();
+
+        for (key, value) in properties.into_iter().flatten() {
+            if let Some(value) = Self::value(value) {
+                tags.push(self.keys.index(key.clone()));
+                tags.push(self.values.index(value));
+            }
+        }
+        tags
+    }
+}
+
+fn zigzag64(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Tile with the single layer `name`, empty when no feature is in the tile
+pub fn encode(tile: &TileId, name: &str, features: &[geojson::Feature]) -> Vec<u8> {
+    let mut layer = Message::default();
+    let mut tags = Tags::default();
+    let mut count = 0;
+
+    layer.uint(15, 2);
+    layer.bytes(1, name.as_bytes());
+
+    for feature in features {
+        let Some(geometry) = &feature.geometry else {
+            continue;
+        };
+        let id = match &feature.id {
+            Some(geojson::feature::Id::Number(number)) => number.as_u64(),
+            _ => None,
+        };
+
+        for (kind, commands) in geometries(tile, &geometry.value) {
+            let mut message = Message::default();
+
+            if let Some(id) = id {
+                message.uint(1, id);
+            }
+            message.packed(2, &tags.tags(feature.properties.as_ref()));
+            message.uint(3, kind);
+            message.packed(4, &commands);
+            layer.bytes(2, &message.0);
+            count += 1;
+        }
+    }
+
+    if count == 0 {
+        return Vec::new();
+    }
+
+    for key in &tags.keys.items {
+        layer.bytes(3, key.as_bytes());
+    }
+    for value in &tags.values.items {
+        layer.bytes(4, value);
+    }
+    layer.uint(5, EXTENT as u64);
+
+    let mut tile = Message::default();
+
+    tile.bytes(3, &layer.0);
+    tile.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zigzag_interleaves_signs() {
+        let encoded: Vec<u32> = [0, -1, 1, -2, 2, -4096, 4095].into_iter().map(zigzag).collect();
+
+        assert_eq!(encoded, [0, 1, 2, 3, 4, 8191, 8190]);
+    }
+
+    #[test]
+    fn commands_pack_id_and_count() {
+        let mut commands = Commands::default();
+
+        commands.ring(&[(2, 2), (2, 6), (6, 6)]);
+        commands.line(&[(1, 1), (3, 3)]);
+
+        assert_eq!(commands.commands, [9, 4, 4, 18, 0, 8, 8, 0, 15, 9, 9, 9, 10, 4, 4]);
+    }
+
+    #[test]
+    fn rings_are_wound_by_their_role() {
+        let tile = TileId::new(1, 1, 0).unwrap();
+        let counterclockwise = vec![
+            vec![10.0, 10.0],
+            vec![40.0, 10.0],
+            vec![40.0, 40.0],
+            vec![10.0, 40.0],
+            vec![10.0, 10.0],
+        ];
+        let clockwise: Vec<Vec<f64>> = counterclockwise.iter().rev().cloned().collect();
+
+        for ring in [&counterclockwise, &clockwise] {
+            let exterior = tile_ring(&tile, ring, true).unwrap();
+            let hole = tile_ring(&tile, ring, false).unwrap();
+
+            assert_eq!(exterior.len(), 4);
+            assert!(ring_area(&exterior) > 0);
+            assert!(ring_area(&hole) < 0);
+        }
+    }
+
+    #[test]
+    fn tags_are_shared_by_features() {
+        let mut tags = Tags::default();
+        let properties = |value: JsonValue| {
+            let mut properties = geojson::JsonObject::new();
+
+            properties.insert("name".to_string(), value);
+            properties
+        };
+
+        let first = tags.tags(Some(&properties(JsonValue::from("a"))));
+        let second = tags.tags(Some(&properties(JsonValue::from("b"))));
+        let third = tags.tags(Some(&properties(JsonValue::from("a"))));
+
+        assert_eq!(first, [0, 0]);
+        assert_eq!(second, [0, 1]);
+        assert_eq!(third, first);
+        assert_eq!(tags.keys.items.len(), 1);
+        assert_eq!(tags.values.items.len(), 2);
+    }
+}
@@ -0,0 +1,375 @@
+//! Retention of stored features and the background job deleting expired ones
+use derivative::Derivative;
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+const FOREVER: &str = "forever";
+
+/// How long features are kept: number of days or "forever"
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(try_from = "RetentionValue", into = "RetentionValue")]
+pub enum Retention {
+    Days(u32),
+    Forever,
+}
+
+/// Retention as it is written in config
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(untagged)]
+enum RetentionValue {
+    Days(u32),
+    Keyword(String),
+}
+
+impl TryFrom<RetentionValue> for Retention {
+    type Error = String;
+
+    fn try_from(value: RetentionValue) -> Result<Self, Self::Error> {
+        match value {
+            RetentionValue::Days(0) => Err(format!(
+                "retention of 0 days would delete every feature, keep them at least 1 day or \"{}\"",
+                FOREVER
+            )),
+            RetentionValue::Days(days) => Ok(Self::Days(days)),
+            RetentionValue::Keyword(keyword) if keyword == FOREVER => Ok(Self::Forever),
+            RetentionValue::Keyword(keyword) => Err(format!(
+                "retention '{}' is neither a number of days nor \"{}\"",
+                keyword, FOREVER
+            )),
+        }
+    }
+}
+
+impl From<Retention> for RetentionValue {
+    fn from(value: Retention) -> Self {
+        match value {
+            Retention::Days(days) => Self::Days(days),
+            Retention::Forever => Self::Keyword(FOREVER.to_string()),
+        }
+    }
+}
+
+impl Retention {
+    /// SQLite `datetime('now', modifier)` of the oldest kept feature, `None` when features are kept forever
+    pub fn modifier(&self) -> Option<String> {
+        match self {
+            Self::Days(days) => Some(format!("-{} day", days)),
+            Self::Forever => None,
+        }
+    }
+}
+
+#[derive(Derivative, serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[derivative(Default)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Retention of channels not listed in `channels`
+    #[derivative(Default(value = "Retention::Days(7)"))]
+    pub default: Retention,
+    /// Retention by channel ids
+    pub channels: BTreeMap<String, Retention>,
+    /// Expired features are deleted with this interval
+    #[derivative(Default(value = "3600"))]
+    pub purge_interval_secs: u64,
+    /// VACUUM and ANALYZE are run after this many purges, 0 to never run them
+    #[derivative(Default(value = "24"))]
+    pub vacuum_every: u32,
+}
+
+impl RetentionConfig {
+    pub fn of(&self, channel: &str) -> Retention {
+        self.channels.get(channel).copied().unwrap_or(self.default)
+    }
+}
+
+/// Marks of purges are tombstones without feature ids, they are not purged themselves
+const NOT_MARK: &str = "NOT (deleted = 1 AND fid IS NULL)";
+
+/// Delete rows of `condition` with their envelopes, `$1` and `$2` of the condition are bound to the arguments.
+/// The row with the greatest id is kept, SQLite would reuse its id: clients' cursors rely on growing ids.
+/// Each channel with deleted rows is marked, returns the number of deleted rows
+async fn delete_rows(
+    transaction: &mut sqlx::SqliteConnection,
+    condition: &str,
+    channels: &str,
+    modifier: &str,
+) -> Result<u64, sqlx::Error> {
+    let condition = format!(
+        "({}) AND {} AND rowid < (SELECT max(rowid) FROM lines)",
+        condition, NOT_MARK
+    );
+
+    let qry = format!("SELECT DISTINCT channel FROM lines WHERE {};", condition);
+    let purged: Vec<String> = sqlx::query_scalar(&qry)
+        .bind(channels)
+        .bind(modifier)
+        .fetch_all(&mut *transaction)
+        .await?;
+
+    let qry = format!(
+        "DELETE FROM lines_bbox WHERE id IN (SELECT rowid FROM lines WHERE {});",
+        condition
+    );
+
+    sqlx::query(&qry)
+        .bind(channels)
+        .bind(modifier)
+        .execute(&mut *transaction)
+        .await?;
+
+    let qry = format!("DELETE FROM lines WHERE {};", condition);
+    let result = sqlx::query(&qry)
+        .bind(channels)
+        .bind(modifier)
+        .execute(&mut *transaction)
+        .await?;
+
+    for channel in purged {
+        mark_purge(transaction, &channel).await?;
+    }
+
+    Ok(result.rows_affected())
+}
+
+/// Replace the channel's mark of the last purge by a new one, its id is greater than ids of purged rows.
+/// The new mark is inserted first: deleting the old one first would free its id for reuse
+async fn mark_purge(transaction: &mut sqlx::SqliteConnection, channel: &str) -> Result<(), sqlx::Error> {
+    let mark = geojson::Feature {
+        bbox: None,
+        geometry: None,
+        id: None,
+        properties: None,
+        foreign_members: None,
+    };
+    let rowid = sqlx::query(
+        "INSERT INTO lines (timestamp, channel, fid, deleted, json) VALUES (datetime('now'), $1, NULL, 1, $2);",
+    )
+    .bind(channel)
+    .bind(mark.to_string())
+    .execute(&mut *transaction)
+    .await?
+    .last_insert_rowid();
+
+    sqlx::query("DELETE FROM lines WHERE channel = $1 AND deleted = 1 AND fid IS NULL AND rowid < $2;")
+        .bind(channel)
+        .bind(rowid)
+        .execute(&mut *transaction)
+        .await?;
+
+    Ok(())
+}
+
+/// Rows of the channel were purged after the client's cursor: changes since the cursor do not tell
+/// which features the client keeps are gone, the client has to download the whole channel
+pub async fn purged_since(pool: &SqlitePool, channel: &str, since: i64) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM lines WHERE channel = $1 AND deleted = 1 AND fid IS NULL AND rowid > $2);",
+    )
+    .bind(channel)
+    .bind(since)
+    .fetch_one(pool)
+    .await
+}
+
+/// Delete features older than retention of their channels
+async fn purge(pool: &SqlitePool, config: &RetentionConfig) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    for (channel, retention) in &config.channels {
+        if let Some(modifier) = retention.modifier() {
+            let removed = delete_rows(
+                &mut transaction,
+                "channel = $1 AND timestamp <= datetime('now', $2)",
+                channel,
+                &modifier,
+            )
+            .await?;
+
+            if removed > 0 {
+                tracing::info!("Purged {} expired features of channel '{}'", removed, channel);
+            }
+        }
+    }
+
+    if let Some(modifier) = config.default.modifier() {
+        let channels = geojson::JsonValue::from(config.channels.keys().cloned().collect::<Vec<_>>()).to_string();
+        let removed = delete_rows(
+            &mut transaction,
+            "channel NOT IN (SELECT value FROM json_each($1)) AND timestamp <= datetime('now', $2)",
+            &channels,
+            &modifier,
+        )
+        .await?;
+
+        if removed > 0 {
+            tracing::info!("Purged {} expired features of other channels", removed);
+        }
+    }
+
+    transaction.commit().await
+}
+
+async fn vacuum(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    /* VACUUM can not run inside of a transaction */
+    sqlx::query("VACUUM;").execute(pool).await?;
+    sqlx::query("ANALYZE;").execute(pool).await?;
+    Ok(())
+}
+
+/// Purge expired features periodically, the first purge is done right away
+pub fn spawn_purge_job(pool: SqlitePool, config: RetentionConfig) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.purge_interval_secs.max(1)));
+        let mut purges: u32 = 0;
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = purge(&pool, &config).await {
+                tracing::error!("Purge of expired features failed: {:?}", e);
+                continue;
+            }
+
+            purges += 1;
+            if config.vacuum_every > 0 && purges.is_multiple_of(config.vacuum_every) {
+                match vacuum(&pool).await {
+                    Ok(_) => tracing::info!("Database vacuumed and analyzed"),
+                    Err(e) => tracing::error!("Vacuum failed: {:?}", e),
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{point, test_state};
+
+    /// Store a feature in the channel `age_days` ago, returns its row id
+    async fn store(pool: &SqlitePool, channel: &str, age_days: u32) -> i64 {
+        let rowid = sqlx::query(
+            "INSERT INTO lines (timestamp, channel, fid, deleted, json) VALUES (datetime('now', $1), $2, NULL, 0, $3);",
+        )
+        .bind(format!("-{} day", age_days))
+        .bind(channel)
+        .bind(point(None).to_string())
+        .execute(pool)
+        .await
+        .unwrap()
+        .last_insert_rowid();
+
+        sqlx::query("UPDATE lines SET fid = rowid WHERE rowid = $1")
+            .bind(rowid)
+            .execute(pool)
+            .await
+            .unwrap();
+        rowid
+    }
+
+    async fn features(pool: &SqlitePool, channel: &str) -> Vec<i64> {
+        sqlx::query_scalar("SELECT rowid FROM lines WHERE channel = $1 AND deleted = 0 ORDER BY rowid;")
+            .bind(channel)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    fn config() -> RetentionConfig {
+        RetentionConfig {
+            channels: BTreeMap::from([
+                ("archive".to_string(), Retention::Forever),
+                ("team".to_string(), Retention::Days(30)),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn expired_features_are_purged_by_retention_of_their_channels() {
+        let state = test_state().await;
+        let pool = &state.sqlite;
+        let _expired = store(pool, "world", 10).await;
+        let kept = store(pool, "world", 1).await;
+        let archived = store(pool, "archive", 100).await;
+        let _team_expired = store(pool, "team", 40).await;
+        let team_kept = store(pool, "team", 10).await;
+
+        purge(pool, &config()).await.unwrap();
+
+        assert_eq!(features(pool, "world").await, vec![kept]);
+        assert_eq!(features(pool, "archive").await, vec![archived]);
+        assert_eq!(features(pool, "team").await, vec![team_kept]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn latest_row_is_kept_to_keep_ids_growing() {
+        let state = test_state().await;
+        let pool = &state.sqlite;
+        let _expired = store(pool, "world", 10).await;
+        let latest = store(pool, "world", 10).await;
+
+        purge(pool, &config()).await.unwrap();
+
+        assert_eq!(features(pool, "world").await, vec![latest]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn purge_is_seen_by_older_cursors_only() {
+        let state = test_state().await;
+        let pool = &state.sqlite;
+        let _expired = store(pool, "world", 10).await;
+        let cursor = store(pool, "world", 1).await;
+        let other = store(pool, "team", 1).await;
+
+        assert!(!purged_since(pool, "world", cursor).await.unwrap());
+
+        purge(pool, &config()).await.unwrap();
+
+        assert!(purged_since(pool, "world", cursor).await.unwrap());
+        assert!(!purged_since(pool, "team", other).await.unwrap());
+
+        /* cursors given after the purge are not older than its mark */
+        let latest: i64 = sqlx::query_scalar("SELECT max(rowid) FROM lines WHERE channel = 'world';")
+            .fetch_one(pool)
+            .await
+            .unwrap();
+
+        assert!(!purged_since(pool, "world", latest).await.unwrap());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn channel_keeps_only_its_last_mark() {
+        let state = test_state().await;
+        let pool = &state.sqlite;
+
+        for _ in 0..3 {
+            store(pool, "world", 10).await;
+            store(pool, "team", 1).await;
+            purge(pool, &config()).await.unwrap();
+        }
+
+        let marks: i64 = sqlx::query_scalar("SELECT count(*) FROM lines WHERE deleted = 1 AND fid IS NULL;")
+            .fetch_one(pool)
+            .await
+            .unwrap();
+
+        assert_eq!(marks, 1);
+    }
+
+    #[test]
+    fn zero_days_are_rejected() {
+        assert!(toml::from_str::<RetentionConfig>("default = 0").is_err());
+        assert_eq!(
+            toml::from_str::<RetentionConfig>("default = 1").unwrap().default,
+            Retention::Days(1)
+        );
+        assert_eq!(
+            toml::from_str::<RetentionConfig>("default = \"forever\"")
+                .unwrap()
+                .default,
+            Retention::Forever
+        );
+    }
+}